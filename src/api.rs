@@ -0,0 +1,374 @@
+//! JSON API under `/api/v1`. It works on the same store as the htmx handlers.
+
+use std::sync::Mutex;
+
+use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse};
+use chrono::NaiveDate;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{
+    audit::{self, Action, AuditEntry},
+    auth::CurrentUser,
+    events::TodoEvent,
+    owns_list, path_id, query_id, schedule_next, store,
+    store::TodoStore,
+    tags,
+    todo::{self, Priority, Recurrence, Todo},
+    ApiError, AppState,
+};
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: &'static str,
+}
+
+fn not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(ErrorBody {
+        error: "todo not found",
+    })
+}
+
+fn invalid(error: &'static str) -> HttpResponse {
+    HttpResponse::UnprocessableEntity().json(ErrorBody { error })
+}
+
+/// Loads the todo of the `{id}` path variable if it belongs to one of the
+/// lists of the user.
+fn find_todo(
+    store: &dyn TodoStore,
+    user: CurrentUser,
+    req: &HttpRequest,
+) -> Result<Option<Todo>, ApiError> {
+    match store.get(path_id(req, "id")?)? {
+        Some(todo) if owns_list(store, user, todo.list_id)? => Ok(Some(todo)),
+        _ => Ok(None),
+    }
+}
+
+#[get("/lists")]
+async fn lists(
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+) -> Result<HttpResponse, ApiError> {
+    let state = data.lock().map_err(|_| ApiError { name: "mutex lock" })?;
+    Ok(HttpResponse::Ok().json(state.store.lists(Some(user.0))?))
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    #[serde(default, deserialize_with = "query_id")]
+    list_id: Option<u128>,
+    /// Lists the archived todos instead.
+    #[serde(default)]
+    archived: bool,
+}
+
+#[get("/todos")]
+async fn list(
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+    query: web::Query<ListQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let state = data.lock().map_err(|_| ApiError { name: "mutex lock" })?;
+    let list_ids = match query.list_id {
+        Some(list_id) if owns_list(&*state.store, user, list_id)? => vec![list_id],
+        Some(_) => vec![],
+        None => state
+            .store
+            .lists(Some(user.0))?
+            .into_iter()
+            .map(|list| list.id)
+            .collect(),
+    };
+    let mut todos = vec![];
+    for list_id in list_ids {
+        todos.extend(match query.archived {
+            true => state.store.archived(list_id)?,
+            false => state.store.list(Some(list_id))?,
+        });
+    }
+    Ok(HttpResponse::Ok().json(todos))
+}
+
+#[get("/todos/{id}")]
+async fn get(
+    req: HttpRequest,
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+) -> Result<HttpResponse, ApiError> {
+    let state = data.lock().map_err(|_| ApiError { name: "mutex lock" })?;
+    match find_todo(&*state.store, user, &req)? {
+        Some(todo) => Ok(HttpResponse::Ok().json(todo)),
+        None => Ok(not_found()),
+    }
+}
+
+#[derive(Deserialize)]
+struct NewTodo {
+    /// Defaults to the first list.
+    list_id: Option<u128>,
+    /// Creates a subtask of this todo in its list.
+    parent_id: Option<u128>,
+    name: String,
+    #[serde(default)]
+    done: bool,
+    #[serde(default)]
+    due: Option<NaiveDate>,
+    #[serde(default)]
+    priority: Priority,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    recurrence: Option<Recurrence>,
+    #[serde(default)]
+    routine: bool,
+}
+
+#[post("/todos")]
+async fn create(
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+    body: web::Json<NewTodo>,
+) -> Result<HttpResponse, ApiError> {
+    let mut state = data.lock().map_err(|_| ApiError { name: "mutex lock" })?;
+    let name = match todo::validate_name(&body.name) {
+        Ok(name) => name,
+        Err(error) => return Ok(invalid(error)),
+    };
+    let tag_names = match todo::validate_tags(body.tags.iter().map(String::as_str)) {
+        Ok(tag_names) => tag_names,
+        Err(error) => return Ok(invalid(error)),
+    };
+    let list_id = match (body.parent_id, body.list_id) {
+        (Some(parent_id), _) => match state.store.get(parent_id)? {
+            Some(parent)
+                if parent.parent_id.is_none()
+                    && !parent.archived
+                    && body.list_id.is_none_or(|list_id| list_id == parent.list_id)
+                    && owns_list(&*state.store, user, parent.list_id)? =>
+            {
+                parent.list_id
+            }
+            _ => {
+                return Ok(invalid(
+                    "The parent todo does not exist, is archived or is a subtask",
+                ))
+            }
+        },
+        (None, Some(list_id)) if owns_list(&*state.store, user, list_id)? => list_id,
+        (None, Some(_)) => return Ok(invalid("The list does not exist")),
+        (None, None) => store::first_list(&mut *state.store, user.0)?.id,
+    };
+    let tags = tags::resolve(&*state.store, user.0, tag_names)?;
+    let todo = state.store.create(Todo {
+        list_id,
+        parent_id: body.parent_id,
+        name,
+        done: body.done,
+        completed: body.done.then(todo::today),
+        due: body.due,
+        priority: body.priority,
+        tags,
+        recurrence: body.recurrence.clone(),
+        routine: body.routine,
+        ..Todo::default()
+    })?;
+    audit::created(&mut state, Some(user.0), &todo)?;
+    state.events.send(match todo.parent_id {
+        Some(_) => TodoEvent::Changed(todo.clone()),
+        None => TodoEvent::Added(todo.clone()),
+    });
+    if todo.done {
+        schedule_next(&mut state, Some(user.0), &todo)?;
+    }
+    Ok(HttpResponse::Created()
+        .append_header(("Location", format!("/api/v1/todos/{}", todo.id)))
+        .json(todo))
+}
+
+/// Distinguishes a field set to `null` from a missing field.
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// Fields of a partial update. Missing fields keep their value, `"due": null`
+/// removes the due date and `"recurrence": null` the repeat rule.
+#[derive(Deserialize)]
+struct TodoChanges {
+    name: Option<String>,
+    done: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    due: Option<Option<NaiveDate>>,
+    priority: Option<Priority>,
+    tags: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    recurrence: Option<Option<Recurrence>>,
+    routine: Option<bool>,
+    archived: Option<bool>,
+}
+
+#[patch("/todos/{id}")]
+async fn update(
+    req: HttpRequest,
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+    body: web::Json<TodoChanges>,
+) -> Result<HttpResponse, ApiError> {
+    let mut state = data.lock().map_err(|_| ApiError { name: "mutex lock" })?;
+    let Some(mut todo) = find_todo(&*state.store, user, &req)? else {
+        return Ok(not_found());
+    };
+    let old = todo.clone();
+    if let Some(name) = &body.name {
+        match todo::validate_name(name) {
+            Ok(name) => todo.name = name,
+            Err(error) => return Ok(invalid(error)),
+        }
+    }
+    let completed = body.done == Some(true) && !todo.done;
+    if let Some(done) = body.done {
+        todo.set_done(done);
+    }
+    if let Some(due) = body.due {
+        todo.due = due;
+    }
+    if let Some(priority) = body.priority {
+        todo.priority = priority;
+    }
+    if let Some(tags) = &body.tags {
+        match todo::validate_tags(tags.iter().map(String::as_str)) {
+            Ok(tag_names) => todo.tags = tags::resolve(&*state.store, user.0, tag_names)?,
+            Err(error) => return Ok(invalid(error)),
+        }
+    }
+    if let Some(recurrence) = &body.recurrence {
+        todo.recurrence = recurrence.clone();
+    }
+    if let Some(routine) = body.routine {
+        todo.routine = routine;
+    }
+    if let Some(archived) = body.archived {
+        todo.archived = archived;
+    }
+    state.store.update(&todo)?;
+    audit::record_changes(&mut state, Some(user.0), &old, &todo)?;
+    if old.archived != todo.archived {
+        // subtasks are archived and restored with their parent
+        let subtasks: Vec<Todo> = match old.archived {
+            true => state.store.archived(todo.list_id)?,
+            false => state.store.list(Some(todo.list_id))?,
+        }
+        .into_iter()
+        .filter(|item| item.parent_id == Some(todo.id))
+        .collect();
+        for old in subtasks {
+            let subtask = Todo {
+                archived: todo.archived,
+                ..old.clone()
+            };
+            state.store.update(&subtask)?;
+            audit::record_changes(&mut state, Some(user.0), &old, &subtask)?;
+        }
+    }
+    state.events.send(TodoEvent::Changed(todo.clone()));
+    if completed {
+        schedule_next(&mut state, Some(user.0), &todo)?;
+    }
+    Ok(HttpResponse::Ok().json(todo))
+}
+
+#[post("/todos/{id}/toggle")]
+async fn toggle(
+    req: HttpRequest,
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+) -> Result<HttpResponse, ApiError> {
+    let mut state = data.lock().map_err(|_| ApiError { name: "mutex lock" })?;
+    // archived todos are restored with PATCH before they can be toggled
+    let Some(old) = find_todo(&*state.store, user, &req)?.filter(|todo| !todo.archived) else {
+        return Ok(not_found());
+    };
+    let mut todo = old.clone();
+    todo.set_done(!old.done);
+    state.store.update(&todo)?;
+    audit::record_changes(&mut state, Some(user.0), &old, &todo)?;
+    state.events.send(TodoEvent::Changed(todo.clone()));
+    if todo.done {
+        schedule_next(&mut state, Some(user.0), &todo)?;
+    }
+    Ok(HttpResponse::Ok().json(todo))
+}
+
+#[delete("/todos/{id}")]
+async fn remove(
+    req: HttpRequest,
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+) -> Result<HttpResponse, ApiError> {
+    let mut state = data.lock().map_err(|_| ApiError { name: "mutex lock" })?;
+    let Some(todo) = find_todo(&*state.store, user, &req)? else {
+        return Ok(not_found());
+    };
+    let removed = state.store.with_subtasks(&todo)?;
+    state.store.remove(todo.id)?;
+    for removed in &removed {
+        audit::record(&mut state, Some(user.0), removed, Action::Deleted)?;
+    }
+    state.events.send(TodoEvent::Removed(todo));
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Serialize)]
+struct HistoryEntry {
+    #[serde(flatten)]
+    entry: AuditEntry,
+    /// Name of the user who made the change, `null` for the server itself.
+    user: Option<String>,
+}
+
+/// The audit log of the todo, the oldest change first. It is kept after the
+/// todo was deleted.
+#[get("/todos/{id}/history")]
+async fn history(
+    req: HttpRequest,
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+) -> Result<HttpResponse, ApiError> {
+    let state = data.lock().map_err(|_| ApiError { name: "mutex lock" })?;
+    let entries = state.store.audit(path_id(&req, "id")?)?;
+    // todos never move to another list
+    let list_id = match entries.first() {
+        Some(entry) => Some(entry.list_id),
+        None => find_todo(&*state.store, user, &req)?.map(|todo| todo.list_id),
+    };
+    match list_id {
+        Some(list_id) if owns_list(&*state.store, user, list_id)? => {}
+        _ => return Ok(not_found()),
+    }
+    let mut history = vec![];
+    for entry in entries {
+        let user = match entry.user_id {
+            Some(id) => state.store.get_user(id)?.map(|user| user.name),
+            None => None,
+        };
+        history.push(HistoryEntry { entry, user });
+    }
+    Ok(HttpResponse::Ok().json(history))
+}
+
+/// All API routes, mounted under `/api/v1`.
+pub fn scope() -> actix_web::Scope {
+    web::scope("/api/v1")
+        .service(lists)
+        .service(list)
+        .service(create)
+        .service(get)
+        .service(history)
+        .service(update)
+        .service(toggle)
+        .service(remove)
+}
@@ -0,0 +1,230 @@
+//! The archive page with the archived todos of all lists of a user, where they
+//! can be searched, restored or deleted for good.
+
+use std::sync::Mutex;
+
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use maud::{html, Markup, DOCTYPE};
+use serde::Deserialize;
+
+use crate::{
+    audit::{self, Action},
+    auth::CurrentUser,
+    events::TodoEvent,
+    owns_list, path_id, search,
+    todo::{Todo, TodoList},
+    ApiError, AppState,
+};
+
+#[derive(Deserialize)]
+struct ArchiveQuery {
+    #[serde(default)]
+    q: String,
+}
+
+/// Loads the archived todo of the `{id}` path variable if it belongs to one of
+/// the lists of the user.
+fn find_archived(
+    state: &AppState,
+    user: CurrentUser,
+    req: &HttpRequest,
+) -> Result<Option<Todo>, ApiError> {
+    match state.store.get(path_id(req, "id")?)? {
+        Some(todo) if todo.archived && owns_list(&*state.store, user, todo.list_id)? => {
+            Ok(Some(todo))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn render_todo(todo: &Todo, subtasks: &[&Todo], query: &str) -> Markup {
+    let id = format!("archived-{}", todo.id);
+    html! {
+        li id=(id) class="flex flex-col gap-1" {
+            div class="flex flex-row gap-4 items-center" {
+                div ."flex-1" .line-through[todo.done] { (search::highlight(&todo.name, query)) }
+                @if let Some(due) = todo.due {
+                    span class="text-sm text-neutral-400" { (due.format("%Y-%m-%d")) }
+                }
+                button class="text-sm text-blue-400" hx-post=(format!("/archive/{}/restore", todo.id)) hx-target=(format!("#{}", id)) hx-swap="outerHTML" { "Restore" }
+                button class="text-sm text-red-500" hx-delete=(format!("/archive/{}", todo.id)) hx-target=(format!("#{}", id)) hx-swap="outerHTML"
+                hx-confirm="Delete the todo for good? This cannot be undone." { "Delete" }
+            }
+            @if !subtasks.is_empty() {
+                ul class="ml-8 flex flex-col gap-1 text-sm text-neutral-400" {
+                    @for subtask in subtasks {
+                        li .line-through[subtask.done] { (search::highlight(&subtask.name, query)) }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The archived todos grouped by list. Subtasks are shown below their archived
+/// parent, a subtask which was archived on its own is shown like a todo. With
+/// a query only the todos are shown whose name or subtasks match.
+fn render_archive(state: &AppState, user: CurrentUser, query: &str) -> Result<Markup, ApiError> {
+    let query = query.trim();
+    let mut lists: Vec<(TodoList, Vec<Todo>)> = vec![];
+    for list in state.store.lists(Some(user.0))? {
+        let todos = state.store.archived(list.id)?;
+        if !todos.is_empty() {
+            lists.push((list, todos));
+        }
+    }
+    let mut groups = vec![];
+    for (list, todos) in &lists {
+        let mut entries = vec![];
+        for todo in todos {
+            if todo
+                .parent_id
+                .is_some_and(|id| todos.iter().any(|parent| parent.id == id))
+            {
+                continue;
+            }
+            let subtasks: Vec<&Todo> = todos
+                .iter()
+                .filter(|subtask| subtask.parent_id == Some(todo.id))
+                .collect();
+            let matched = query.is_empty()
+                || search::matches(&todo.name, query)
+                || subtasks
+                    .iter()
+                    .any(|subtask| search::matches(&subtask.name, query));
+            if matched {
+                entries.push((todo, subtasks));
+            }
+        }
+        if !entries.is_empty() {
+            groups.push((list, entries));
+        }
+    }
+    Ok(html! {
+        div #archive class="flex flex-col gap-4" {
+            @if groups.is_empty() {
+                p class="text-neutral-400" {
+                    @if query.is_empty() {
+                        "The archive is empty. \"Archive completed\" and the daily reset move completed todos here."
+                    } @else {
+                        "No archived todo matches the search."
+                    }
+                }
+            }
+            @for (list, entries) in &groups {
+                section class="flex flex-col gap-2" {
+                    h2 class="text-lg" { a href=(list.url()) { (list.title) } }
+                    ul class="flex flex-col gap-2" {
+                        @for (todo, subtasks) in entries {
+                            (render_todo(todo, subtasks, query))
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[get("/archive")]
+async fn page(
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+    query: web::Query<ArchiveQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let body = html! {
+        (DOCTYPE)
+        script src="/assets/tailwind.min.js" {}
+        script src="/assets/htmx.min.js"{}
+        link rel="icon" type="image/png" href="/assets/favicon.png";
+        link src="/assets/global.css" rel="stylesheet" {}
+        title { "Archive - Todo" }
+
+        body ."min-h-sreen" .text-white .bg-black ."p-4" {
+            main class="container m-auto max-w-2xl flex flex-col gap-4" {
+                a href="/" class="text-sm text-neutral-400" { "← Lists" }
+                h1 class="text-2xl" { "Archive" }
+                input type="search" name="q" value=(query.q) placeholder="Search the archive"
+                class="border rounded border-neutral-400 px-2 py-1 bg-black"
+                hx-get="/archive/items"
+                hx-trigger="keyup changed delay:300ms, search"
+                hx-target="#archive" hx-swap="outerHTML";
+                (render_archive(&state, user, &query.q)?)
+            }
+        }
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body.into_string()))
+}
+
+#[get("/archive/items")]
+async fn items(
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+    query: web::Query<ArchiveQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    Ok(HttpResponse::Ok().body(render_archive(&state, user, &query.q)?.into_string()))
+}
+
+/// Moves the todo back into its list, together with its archived subtasks.
+#[post("/archive/{id}/restore")]
+async fn restore(
+    req: HttpRequest,
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+) -> Result<HttpResponse, ApiError> {
+    let mut state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let Some(todo) = find_archived(&state, user, &req)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let todos: Vec<Todo> = state
+        .store
+        .archived(todo.list_id)?
+        .into_iter()
+        .filter(|item| item.id == todo.id || item.parent_id == Some(todo.id))
+        .collect();
+    for old in todos {
+        let todo = Todo {
+            archived: false,
+            ..old
+        };
+        state.store.update(&todo)?;
+        audit::record(&mut state, Some(user.0), &todo, Action::Restored)?;
+    }
+    state.events.send(TodoEvent::ListChanged(todo.list_id));
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Deletes the archived todo and its subtasks for good.
+#[delete("/archive/{id}")]
+async fn remove(
+    req: HttpRequest,
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+) -> Result<HttpResponse, ApiError> {
+    let mut state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let Some(todo) = find_archived(&state, user, &req)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let removed = state.store.with_subtasks(&todo)?;
+    state.store.remove(todo.id)?;
+    for todo in &removed {
+        audit::record(&mut state, Some(user.0), todo, Action::Deleted)?;
+    }
+    state.events.send(TodoEvent::ListChanged(todo.list_id));
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,195 @@
+//! Append-only log of the changes to every todo, shown in the history panel of
+//! the todo and served by the JSON API.
+
+use std::sync::Mutex;
+
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use maud::{html, Markup};
+use serde::{Deserialize, Serialize};
+
+use crate::{find_todo, store::StoreError, todo::Todo, ApiError, AppState};
+
+/// What happened to the todo.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum Action {
+    Created {
+        name: String,
+    },
+    Renamed {
+        from: String,
+        to: String,
+    },
+    Completed,
+    Reopened,
+    Rescheduled {
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    },
+    /// Other fields changed, e.g. the priority or the tags.
+    Changed {
+        fields: Vec<String>,
+    },
+    Deleted,
+    /// The todo was moved to the archive.
+    Archived,
+    /// The todo was brought back after it was deleted or archived.
+    Restored,
+}
+
+/// One change of a todo. The list is kept, so the log of a deleted todo can
+/// still be checked against the owner of the list.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditEntry {
+    pub todo_id: u128,
+    pub list_id: u128,
+    pub time: DateTime<Utc>,
+    /// The user who made the change, `None` for changes of the server itself
+    /// like the daily reset.
+    pub user_id: Option<u128>,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+/// Appends the action on the todo to the log.
+pub fn record(
+    state: &mut AppState,
+    user_id: Option<u128>,
+    todo: &Todo,
+    action: Action,
+) -> Result<(), StoreError> {
+    state.store.append_audit(&AuditEntry {
+        todo_id: todo.id,
+        list_id: todo.list_id,
+        time: Utc::now(),
+        user_id,
+        action,
+    })
+}
+
+/// Appends the creation of the todo to the log.
+pub fn created(state: &mut AppState, user_id: Option<u128>, todo: &Todo) -> Result<(), StoreError> {
+    let name = todo.name.clone();
+    record(state, user_id, todo, Action::Created { name })
+}
+
+/// Appends the differences between the old and the new version of the todo.
+pub fn record_changes(
+    state: &mut AppState,
+    user_id: Option<u128>,
+    old: &Todo,
+    new: &Todo,
+) -> Result<(), StoreError> {
+    let mut actions = vec![];
+    if old.name != new.name {
+        actions.push(Action::Renamed {
+            from: old.name.clone(),
+            to: new.name.clone(),
+        });
+    }
+    if old.done != new.done {
+        actions.push(match new.done {
+            true => Action::Completed,
+            false => Action::Reopened,
+        });
+    }
+    if old.due != new.due {
+        actions.push(Action::Rescheduled {
+            from: old.due,
+            to: new.due,
+        });
+    }
+    if old.archived != new.archived {
+        actions.push(match new.archived {
+            true => Action::Archived,
+            false => Action::Restored,
+        });
+    }
+    let fields: Vec<String> = [
+        ("priority", old.priority != new.priority),
+        ("tags", old.tags != new.tags),
+        ("recurrence", old.recurrence != new.recurrence),
+        ("routine", old.routine != new.routine),
+    ]
+    .into_iter()
+    .filter(|(_, changed)| *changed)
+    .map(|(field, _)| field.to_string())
+    .collect();
+    if !fields.is_empty() {
+        actions.push(Action::Changed { fields });
+    }
+    for action in actions {
+        record(state, user_id, new, action)?;
+    }
+    Ok(())
+}
+
+/// Describes the action, e.g. "renamed it from "a" to "b"".
+fn describe(action: &Action) -> String {
+    let date = |date: &Option<NaiveDate>| match date {
+        Some(date) => date.to_string(),
+        None => "no date".to_string(),
+    };
+    match action {
+        Action::Created { name } => format!("created \"{}\"", name),
+        Action::Renamed { from, to } => format!("renamed it from \"{}\" to \"{}\"", from, to),
+        Action::Completed => "marked it done".to_string(),
+        Action::Reopened => "marked it open".to_string(),
+        Action::Rescheduled { from, to } => {
+            format!("moved the due date from {} to {}", date(from), date(to))
+        }
+        Action::Changed { fields } => format!("changed the {}", fields.join(", ")),
+        Action::Deleted => "deleted it".to_string(),
+        Action::Archived => "archived it".to_string(),
+        Action::Restored => "restored it".to_string(),
+    }
+}
+
+/// The log of the todo with the names of the users, the latest change first.
+/// It replaces the placeholder below the item, "Hide" renders the item again.
+fn render_history(state: &AppState, todo: &Todo) -> Result<Markup, StoreError> {
+    let mut rows = vec![];
+    for entry in state.store.audit(todo.id)?.iter().rev() {
+        let user = match entry.user_id {
+            Some(id) => state.store.get_user(id)?.map(|user| user.name),
+            None => Some("The server".to_string()),
+        };
+        rows.push((
+            entry.time,
+            user.unwrap_or_else(|| "An unknown user".to_string()),
+            describe(&entry.action),
+        ));
+    }
+    Ok(html! {
+        div id=(format!("history-{}", todo.id)) class="ml-8 flex flex-row gap-4 items-start text-sm text-neutral-400" {
+            ul class="flex-1 flex flex-col" {
+                @if rows.is_empty() {
+                    li { "No changes recorded." }
+                }
+                @for (time, user, action) in &rows {
+                    li {
+                        span class="text-neutral-500" { (time.with_timezone(&Local).format("%Y-%m-%d %H:%M")) }
+                        " " (user) " " (action)
+                    }
+                }
+            }
+            button hx-get=(todo.url()) hx-target=(format!("#todo-{}", todo.id)) hx-swap="outerHTML" { "Hide" }
+        }
+    })
+}
+
+#[get("/{id}/history")]
+async fn history(
+    req: HttpRequest,
+    data: web::Data<Mutex<AppState>>,
+) -> Result<HttpResponse, ApiError> {
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    match find_todo(&*state.store, &req)? {
+        Some(todo) => Ok(HttpResponse::Ok().body(render_history(&state, &todo)?.into_string())),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
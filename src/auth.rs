@@ -0,0 +1,325 @@
+//! User accounts: registration, login with session cookies and the middleware
+//! which keeps unauthenticated requests out.
+
+use std::{
+    future::{ready, Ready},
+    sync::Mutex,
+};
+
+use actix_session::{storage::CookieSessionStore, Session, SessionExt, SessionMiddleware};
+use actix_web::{
+    body::{BoxBody, EitherBody, MessageBody},
+    cookie::Key,
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    get,
+    http::header,
+    middleware::Next,
+    post, web, FromRequest, HttpMessage, HttpRequest, HttpResponse, Responder,
+};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use maud::{html, Markup, DOCTYPE};
+use serde::{Deserialize, Serialize};
+
+use crate::{ApiError, AppState};
+
+/// Session key of the id of the logged in user.
+const USER_KEY: &str = "user_id";
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct User {
+    pub id: u128,
+    pub name: String,
+    pub password_hash: String,
+}
+
+/// Hashes the password with Argon2 and a random salt.
+pub fn hash_password(password: &str) -> Result<String, ApiError> {
+    let salt = SaltString::generate(&mut OsRng);
+    match Argon2::default().hash_password(password.as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+        Err(_) => Err(ApiError {
+            name: "password hash",
+        }),
+    }
+}
+
+pub fn verify_password(user: &User, password: &str) -> bool {
+    match PasswordHash::new(&user.password_hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Trims the user name and checks that it only uses letters, digits, `-` and `_`.
+fn validate_user_name(name: &str) -> Result<String, &'static str> {
+    let name = name.trim();
+    if name.chars().count() < 3 || name.chars().count() > 32 {
+        return Err("The user name must have between 3 and 32 characters");
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err("The user name may only contain letters, digits, '-' and '_'");
+    }
+    Ok(name.to_string())
+}
+
+/// Id of the authenticated user. Handlers behind [`require_login`] can
+/// always extract it.
+#[derive(Clone, Copy)]
+pub(crate) struct CurrentUser(pub u128);
+
+impl FromRequest for CurrentUser {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        // set by `require_login`
+        let user = req.extensions().get::<CurrentUser>().copied();
+        ready(user.ok_or(ApiError {
+            name: "not logged in",
+        }))
+    }
+}
+
+/// Reads the user of the session cookie. The session of a user who does not
+/// exist is purged, e.g. after a restart of the memory store, which would
+/// otherwise hand the id to the next user who registers.
+fn session_user(req: &ServiceRequest) -> Option<CurrentUser> {
+    let session = req.get_session();
+    let id = session.get::<u128>(USER_KEY).ok().flatten()?;
+    let data = req.app_data::<web::Data<Mutex<AppState>>>()?;
+    let user = data.lock().ok()?.store.get_user(id).ok()?;
+    if user.is_none() {
+        session.purge();
+    }
+    user.map(|user| CurrentUser(user.id))
+}
+
+/// Checks `Authorization: Basic ...` credentials against the stored users.
+fn basic_auth(req: &ServiceRequest) -> Option<CurrentUser> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let decoded = STANDARD.decode(value.strip_prefix("Basic ")?).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (name, password) = decoded.split_once(':')?;
+    let data = req.app_data::<web::Data<Mutex<AppState>>>()?;
+    // clone the user so the lock is not held while verifying the password
+    let user = data.lock().ok()?.store.find_user(name).ok()??;
+    verify_password(&user, password).then_some(CurrentUser(user.id))
+}
+
+/// Paths for API clients, calendar apps and CalDAV clients, which cannot log
+/// in with a form.
+fn accepts_basic_auth(path: &str) -> bool {
+    path.starts_with("/api/")
+        || path.ends_with(".ics")
+        || path == "/caldav"
+        || path.starts_with("/caldav/")
+        || path == "/.well-known/caldav"
+}
+
+fn is_public(path: &str) -> bool {
+    path == "/login" || path == "/register" || path.starts_with("/assets/")
+}
+
+/// Lets only authenticated requests through. Browsers are redirected to the
+/// login page, API clients, calendar apps and CalDAV clients get a `401` and
+/// may also use basic auth.
+pub async fn require_login(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody, BoxBody>>, actix_web::Error> {
+    if is_public(req.path()) {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+    if let Some(user) = session_user(&req) {
+        req.extensions_mut().insert(user);
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+    if accepts_basic_auth(req.path()) {
+        if let Some(user) = basic_auth(&req) {
+            req.extensions_mut().insert(user);
+            return Ok(next.call(req).await?.map_into_left_body());
+        }
+        let response = HttpResponse::Unauthorized()
+            .append_header((header::WWW_AUTHENTICATE, "Basic realm=\"todos\""))
+            .json(serde_json::json!({ "error": "not logged in" }));
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+    let response = redirect(req.request(), "/login");
+    Ok(req.into_response(response).map_into_right_body())
+}
+
+/// Redirects the browser. htmx requests need the `HX-Redirect` header,
+/// otherwise the target page would be swapped into the current page.
+pub fn redirect(req: &HttpRequest, location: &str) -> HttpResponse {
+    if req.headers().contains_key("HX-Request") {
+        return HttpResponse::Ok()
+            .append_header(("HX-Redirect", location))
+            .finish();
+    }
+    HttpResponse::SeeOther()
+        .append_header((header::LOCATION, location))
+        .finish()
+}
+
+fn render_page(title: &str, action: &str, error: Option<&str>, name: &str) -> Markup {
+    let (switch_text, switch_url, switch_label) = if action == "/login" {
+        ("No account yet?", "/register", "Register")
+    } else {
+        ("Already registered?", "/login", "Login")
+    };
+    html! {
+        (DOCTYPE)
+        script src="/assets/tailwind.min.js" {}
+        link rel="icon" type="image/png" href="/assets/favicon.png";
+        title {
+            (title) " - Todo"
+        }
+
+        body ."min-h-sreen" .text-white .bg-black ."p-4" {
+        main class="container m-auto flex flex-col gap-4 max-w-sm" {
+            h1 class="text-2xl" {
+                (title)
+            }
+            form class="flex flex-col gap-4" method="post" action=(action) {
+                input name="name" value=(name) placeholder="User name" autofocus class="border rounded border-neutral-400 text-sm px-4 py-2 bg-black" ;
+                input name="password" type="password" placeholder="Password" class="border rounded border-neutral-400 text-sm px-4 py-2 bg-black" ;
+                @if let Some(error) = error {
+                    div class="text-sm text-red-500" { (error) }
+                }
+                button class="rounded bg-blue-500 px-4 py-2" {(title)}
+            }
+            div class="text-sm text-neutral-400" {
+                (switch_text) " " a class="underline" href=(switch_url) {(switch_label)}
+            }
+        }
+        }
+    }
+}
+
+/// Reads the key to sign the session cookies from `SESSION_KEY`, which must
+/// have at least 64 bytes. Without it a random key is used and all sessions
+/// end with a restart.
+pub fn session_key() -> std::io::Result<Key> {
+    match std::env::var("SESSION_KEY") {
+        Ok(key) => Key::try_from(key.as_bytes())
+            .map_err(|_| std::io::Error::other("SESSION_KEY must have at least 64 bytes")),
+        Err(_) => {
+            println!("SESSION_KEY is not set, sessions end with a restart of the server");
+            Ok(Key::generate())
+        }
+    }
+}
+
+pub fn session_middleware(key: Key) -> SessionMiddleware<CookieSessionStore> {
+    SessionMiddleware::builder(CookieSessionStore::default(), key)
+        // the server is usually reached via plain http
+        .cookie_secure(false)
+        .build()
+}
+
+#[derive(Deserialize)]
+struct Credentials {
+    name: String,
+    password: String,
+}
+
+#[get("/login")]
+async fn login_form() -> Markup {
+    render_page("Login", "/login", None, "")
+}
+
+#[post("/login")]
+async fn login(
+    data: web::Data<Mutex<AppState>>,
+    session: Session,
+    form: web::Form<Credentials>,
+) -> Result<HttpResponse, ApiError> {
+    let user = match data.lock() {
+        Ok(state) => state.store.find_user(form.name.trim())?,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    match user {
+        Some(user) if verify_password(&user, &form.password) => {
+            session.renew();
+            session
+                .insert(USER_KEY, user.id)
+                .map_err(|_| ApiError { name: "session" })?;
+            Ok(HttpResponse::SeeOther()
+                .append_header((header::LOCATION, "/"))
+                .finish())
+        }
+        _ => Ok(HttpResponse::Ok().body(
+            render_page(
+                "Login",
+                "/login",
+                Some("Unknown user name or wrong password"),
+                &form.name,
+            )
+            .into_string(),
+        )),
+    }
+}
+
+#[get("/register")]
+async fn register_form() -> Markup {
+    render_page("Register", "/register", None, "")
+}
+
+#[post("/register")]
+async fn register(
+    data: web::Data<Mutex<AppState>>,
+    session: Session,
+    form: web::Form<Credentials>,
+) -> Result<HttpResponse, ApiError> {
+    let error = |error| {
+        Ok(HttpResponse::Ok()
+            .body(render_page("Register", "/register", Some(error), &form.name).into_string()))
+    };
+    let name = match validate_user_name(&form.name) {
+        Ok(name) => name,
+        Err(err) => return error(err),
+    };
+    if form.password.chars().count() < MIN_PASSWORD_LENGTH {
+        return error("The password must have at least 8 characters");
+    }
+    let password_hash = hash_password(&form.password)?;
+    let user = {
+        let mut state = match data.lock() {
+            Ok(state) => state,
+            Err(_) => return Err(ApiError { name: "mutex lock" }),
+        };
+        if state.store.find_user(&name)?.is_some() {
+            return error("The user name is already taken");
+        }
+        let first = !state.store.has_users()?;
+        let user = state.store.create_user(&name, &password_hash)?;
+        // lists created before there were accounts belong to the first user
+        if first {
+            state.store.adopt_lists(user.id)?;
+        }
+        user
+    };
+    session.renew();
+    session
+        .insert(USER_KEY, user.id)
+        .map_err(|_| ApiError { name: "session" })?;
+    Ok(HttpResponse::SeeOther()
+        .append_header((header::LOCATION, "/"))
+        .finish())
+}
+
+#[post("/logout")]
+async fn logout(req: HttpRequest, session: Session) -> impl Responder {
+    session.purge();
+    redirect(&req, "/login")
+}
@@ -0,0 +1,799 @@
+//! CalDAV (RFC 4791) for task apps: every list of the user is a calendar of
+//! `VTODO` resources under `/caldav/{list_id}/`, which apps can read and
+//! change with PROPFIND, REPORT, GET, PUT and DELETE.
+//!
+//! `/caldav/` is both the principal of the user and the home of the
+//! calendars. Todos added by an app keep the name the app chose for their
+//! resource, the others are named after their `UID`, e.g.
+//! `/caldav/1/todo-7@htmx-rust.ics`. The ETag of a resource is a hash of the
+//! todo.
+//! Changes made through CalDAV are recorded in the change history and sent to
+//! the open pages of the list like changes in the web frontend.
+
+use std::sync::Mutex;
+
+use actix_web::{
+    guard,
+    http::{header, Method, StatusCode},
+    web, HttpRequest, HttpResponse, Route,
+};
+use roxmltree::{Document, Node};
+
+use crate::{
+    audit,
+    auth::CurrentUser,
+    events::TodoEvent,
+    ical, owns_list, schedule_next, tags,
+    todo::{self, Todo, TodoList},
+    ApiError, AppState,
+};
+
+const DAV: &str = "DAV:";
+const CALDAV: &str = "urn:ietf:params:xml:ns:caldav";
+const CALENDARSERVER: &str = "http://calendarserver.org/ns/";
+
+const HOME: &str = "/caldav/";
+const ALLOW: &str = "OPTIONS, GET, PUT, DELETE, PROPFIND, REPORT";
+
+/// A property of a resource with its value as XML.
+struct Property {
+    namespace: &'static str,
+    name: &'static str,
+    value: String,
+}
+
+fn property(namespace: &'static str, name: &'static str, value: impl Into<String>) -> Property {
+    Property {
+        namespace,
+        name,
+        value: value.into(),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        // XML parsers turn line breaks into `\n`, iCalendar needs `\r\n`
+        .replace('\r', "&#13;")
+}
+
+/// Percent-encodes a path segment, e.g. a `UID` with spaces or slashes.
+fn encode_segment(segment: &str) -> String {
+    let mut encoded = String::new();
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'@' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn decode_segment(segment: &str) -> String {
+    let mut bytes = vec![];
+    let mut rest = segment.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let hex = tail.get(..2).and_then(|hex| std::str::from_utf8(hex).ok());
+        match hex.map(|hex| u8::from_str_radix(hex, 16)) {
+            Some(Ok(decoded)) if byte == b'%' => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn calendar_href(list_id: u128) -> String {
+    format!("{}{}/", HOME, list_id)
+}
+
+/// The name of the resource of the todo, percent-encoded.
+fn resource_name(todo: &Todo) -> String {
+    match &todo.resource {
+        Some(name) => name.clone(),
+        None => format!("{}.ics", encode_segment(&ical::uid(todo))),
+    }
+}
+
+fn todo_href(todo: &Todo) -> String {
+    format!("{}{}", calendar_href(todo.list_id), resource_name(todo))
+}
+
+/// The last segment of the path or the href, the name of a resource.
+fn last_segment(href: &str) -> &str {
+    href.trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+}
+
+/// Finds the todo of a resource name like `todo-7@htmx-rust.ics`. The names
+/// are compared decoded, as apps encode them differently.
+fn find_resource<'a>(todos: &'a [Todo], href: &str) -> Option<&'a Todo> {
+    let name = decode_segment(last_segment(href));
+    todos
+        .iter()
+        .find(|todo| decode_segment(&resource_name(todo)) == name)
+}
+
+fn parent<'a>(todos: &'a [Todo], todo: &Todo) -> Option<&'a Todo> {
+    todos
+        .iter()
+        .find(|parent| Some(parent.id) == todo.parent_id)
+}
+
+/// The 64 bit FNV-1a hash. Unlike the hasher of the standard library it does
+/// not change with the Rust version, so the ETags survive updates.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    /// Adds the text and a separator, so `"ab", "c"` and `"a", "bc"` differ.
+    fn write(&mut self, text: &str) {
+        for byte in text.bytes().chain([0xff]) {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn tag(&self) -> String {
+        format!("\"{:016x}\"", self.0)
+    }
+}
+
+/// A hash of the todo, which changes with every change of the todo.
+fn etag(todo: &Todo, parent: Option<&Todo>) -> String {
+    let mut hash = Fnv::new();
+    hash.write(&serde_json::to_string(todo).unwrap_or_default());
+    hash.write(&parent.map(ical::uid).unwrap_or_default());
+    hash.tag()
+}
+
+/// A hash of the list and its todos, which apps compare to find out whether
+/// anything changed since the last sync.
+fn ctag(list: &TodoList, todos: &[Todo]) -> String {
+    let mut hash = Fnv::new();
+    hash.write(&list.title);
+    for todo in todos {
+        hash.write(&etag(todo, parent(todos, todo)));
+    }
+    hash.tag()
+}
+
+fn calendar_data(list: &TodoList, todos: &[Todo], todo: &Todo) -> String {
+    let parents: Vec<&Todo> = parent(todos, todo).into_iter().collect();
+    ical::format_calendar(&list.title, &[todo], &parents)
+}
+
+fn home_properties(user_name: &str) -> Vec<Property> {
+    let href = format!("<d:href>{}</d:href>", HOME);
+    vec![
+        property(DAV, "resourcetype", "<d:collection/><d:principal/>"),
+        property(DAV, "displayname", escape(user_name)),
+        property(DAV, "current-user-principal", href.clone()),
+        property(DAV, "principal-URL", href.clone()),
+        property(CALDAV, "calendar-home-set", href),
+    ]
+}
+
+fn calendar_properties(list: &TodoList, todos: &[Todo]) -> Vec<Property> {
+    let href = format!("<d:href>{}</d:href>", HOME);
+    let privileges: String = ["read", "write", "write-content", "bind", "unbind"]
+        .iter()
+        .map(|privilege| format!("<d:privilege><d:{}/></d:privilege>", privilege))
+        .collect();
+    let reports: String = ["calendar-query", "calendar-multiget"]
+        .iter()
+        .map(|report| {
+            format!(
+                "<d:supported-report><d:report><c:{}/></d:report></d:supported-report>",
+                report
+            )
+        })
+        .collect();
+    let ctag = ctag(list, todos);
+    vec![
+        property(DAV, "resourcetype", "<d:collection/><c:calendar/>"),
+        property(DAV, "displayname", escape(&list.title)),
+        property(DAV, "current-user-principal", href.clone()),
+        property(DAV, "owner", href),
+        property(DAV, "current-user-privilege-set", privileges),
+        property(DAV, "supported-report-set", reports),
+        property(DAV, "getetag", escape(&ctag)),
+        property(CALENDARSERVER, "getctag", escape(&ctag)),
+        property(
+            CALDAV,
+            "supported-calendar-component-set",
+            "<c:comp name=\"VTODO\"/>",
+        ),
+    ]
+}
+
+fn todo_properties(list: &TodoList, todos: &[Todo], todo: &Todo) -> Vec<Property> {
+    vec![
+        property(DAV, "resourcetype", ""),
+        property(DAV, "getetag", escape(&etag(todo, parent(todos, todo)))),
+        property(
+            DAV,
+            "getcontenttype",
+            "text/calendar; charset=utf-8; component=VTODO",
+        ),
+        property(
+            CALDAV,
+            "calendar-data",
+            escape(&calendar_data(list, todos, todo)),
+        ),
+    ]
+}
+
+/// The names of the properties in the `prop` element of a PROPFIND or REPORT
+/// body. `None` for all properties, e.g. for an empty body or `allprop`.
+fn requested(document: Option<&Document>) -> Option<Vec<(String, String)>> {
+    let prop = document?
+        .root_element()
+        .children()
+        .find(|node| node.has_tag_name((DAV, "prop")))?;
+    let names = prop
+        .children()
+        .filter(Node::is_element)
+        .map(|node| {
+            let name = node.tag_name();
+            (
+                name.namespace().unwrap_or_default().to_string(),
+                name.name().to_string(),
+            )
+        })
+        .collect();
+    Some(names)
+}
+
+fn prefix(namespace: &str) -> &'static str {
+    match namespace {
+        CALDAV => "c",
+        CALENDARSERVER => "cs",
+        _ => "d",
+    }
+}
+
+fn propstat(properties: &str, status: &str) -> String {
+    format!(
+        "<d:propstat><d:prop>{}</d:prop><d:status>HTTP/1.1 {}</d:status></d:propstat>",
+        properties, status
+    )
+}
+
+/// The `response` element of a resource with the requested properties. Without
+/// a request all properties but the calendar data are returned, unknown
+/// properties are listed as not found.
+fn response(
+    href: &str,
+    properties: Vec<Property>,
+    requested: Option<&[(String, String)]>,
+) -> String {
+    let mut found = String::new();
+    let mut missing = String::new();
+    let mut push = |property: &Property| {
+        let name = format!("{}:{}", prefix(property.namespace), property.name);
+        match property.value.is_empty() {
+            true => found.push_str(&format!("<{}/>", name)),
+            false => found.push_str(&format!("<{}>{}</{}>", name, property.value, name)),
+        }
+    };
+    match requested {
+        None => properties
+            .iter()
+            .filter(|property| property.name != "calendar-data")
+            .for_each(&mut push),
+        Some(names) => {
+            for (namespace, name) in names {
+                match properties
+                    .iter()
+                    .find(|property| property.namespace == namespace && property.name == name)
+                {
+                    Some(property) => push(property),
+                    None => missing.push_str(&format!(
+                        "<x:{} xmlns:x=\"{}\"/>",
+                        name,
+                        escape(namespace)
+                    )),
+                }
+            }
+        }
+    }
+    let mut xml = format!("<d:response><d:href>{}</d:href>", escape(href));
+    if !found.is_empty() || missing.is_empty() {
+        xml.push_str(&propstat(&found, "200 OK"));
+    }
+    if !missing.is_empty() {
+        xml.push_str(&propstat(&missing, "404 Not Found"));
+    }
+    xml.push_str("</d:response>");
+    xml
+}
+
+fn multistatus(responses: &[String]) -> HttpResponse {
+    let body = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<d:multistatus xmlns:d=\"{}\" xmlns:c=\"{}\" xmlns:cs=\"{}\">{}</d:multistatus>",
+        DAV,
+        CALDAV,
+        CALENDARSERVER,
+        responses.concat()
+    );
+    HttpResponse::build(StatusCode::MULTI_STATUS)
+        .content_type("application/xml; charset=utf-8")
+        .body(body)
+}
+
+/// A `403` naming the precondition which failed, e.g. `c:valid-calendar-data`.
+fn forbidden(precondition: &str) -> HttpResponse {
+    let body = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<d:error xmlns:d=\"{}\" xmlns:c=\"{}\"><{}/></d:error>",
+        DAV, CALDAV, precondition
+    );
+    HttpResponse::Forbidden()
+        .content_type("application/xml; charset=utf-8")
+        .body(body)
+}
+
+/// Parses the XML body, `Ok(None)` for an empty body.
+fn parse_body(body: &str) -> Result<Option<Document<'_>>, roxmltree::Error> {
+    if body.trim().is_empty() {
+        return Ok(None);
+    }
+    Document::parse(body).map(Some)
+}
+
+fn invalid_xml() -> HttpResponse {
+    HttpResponse::BadRequest().body("The body is no valid XML")
+}
+
+/// Whether a PROPFIND includes the members of the collection. Clients send
+/// `Depth: 0` or `1`, `infinity` is answered like `1`.
+fn includes_members(req: &HttpRequest) -> bool {
+    req.headers()
+        .get("Depth")
+        .and_then(|depth| depth.to_str().ok())
+        != Some("0")
+}
+
+/// Checks `If-Match` and `If-None-Match` against the ETag of the resource,
+/// `None` if it does not exist yet.
+fn precondition_failed(req: &HttpRequest, etag: Option<&str>) -> bool {
+    let header = |name| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+    };
+    let matches = |value: &str| {
+        value == "*" || etag.is_some_and(|etag| value.split(',').any(|tag| tag.trim() == etag))
+    };
+    let if_match = header(header::IF_MATCH).is_some_and(|value| etag.is_none() || !matches(value));
+    let if_none_match =
+        header(header::IF_NONE_MATCH).is_some_and(|value| etag.is_some() && matches(value));
+    if_match || if_none_match
+}
+
+/// Loads the list of the `{list_id}` path variable with its todos if it
+/// belongs to the user. Archived todos are left out.
+fn find_list(
+    state: &AppState,
+    user: CurrentUser,
+    req: &HttpRequest,
+) -> Result<Option<(TodoList, Vec<Todo>)>, ApiError> {
+    let list_id = req.match_info().get("list_id").map(str::parse::<u128>);
+    let Some(Ok(list_id)) = list_id else {
+        return Ok(None);
+    };
+    if !owns_list(&*state.store, user, list_id)? {
+        return Ok(None);
+    }
+    let Some(list) = state.store.get_list(list_id)? else {
+        return Ok(None);
+    };
+    let todos = state.store.list(Some(list_id))?;
+    Ok(Some((list, todos)))
+}
+
+async fn options() -> HttpResponse {
+    HttpResponse::Ok()
+        .append_header(("DAV", "1, 3, calendar-access"))
+        .append_header((header::ALLOW, ALLOW))
+        .finish()
+}
+
+/// Apps look for the CalDAV server at this address.
+async fn well_known() -> HttpResponse {
+    HttpResponse::MovedPermanently()
+        .append_header((header::LOCATION, HOME))
+        .finish()
+}
+
+/// The principal and calendar home, with the lists as calendars.
+async fn propfind_home(
+    req: HttpRequest,
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let document = match parse_body(&body) {
+        Ok(document) => document,
+        Err(_) => return Ok(invalid_xml()),
+    };
+    let requested = requested(document.as_ref());
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let user_name = state
+        .store
+        .get_user(user.0)?
+        .map(|user| user.name)
+        .unwrap_or_default();
+    let mut responses = vec![response(
+        HOME,
+        home_properties(&user_name),
+        requested.as_deref(),
+    )];
+    // a PROPFIND does not change anything, a user without lists has no
+    // calendars until the first visit of the web frontend creates one
+    if includes_members(&req) {
+        for list in state.store.lists(Some(user.0))? {
+            let todos = state.store.list(Some(list.id))?;
+            responses.push(response(
+                &calendar_href(list.id),
+                calendar_properties(&list, &todos),
+                requested.as_deref(),
+            ));
+        }
+    }
+    Ok(multistatus(&responses))
+}
+
+/// The calendar of a list, with its todos as resources.
+async fn propfind_calendar(
+    req: HttpRequest,
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let document = match parse_body(&body) {
+        Ok(document) => document,
+        Err(_) => return Ok(invalid_xml()),
+    };
+    let requested = requested(document.as_ref());
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let Some((list, todos)) = find_list(&state, user, &req)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let mut responses = vec![response(
+        &calendar_href(list.id),
+        calendar_properties(&list, &todos),
+        requested.as_deref(),
+    )];
+    if includes_members(&req) {
+        for todo in &todos {
+            responses.push(response(
+                &todo_href(todo),
+                todo_properties(&list, &todos, todo),
+                requested.as_deref(),
+            ));
+        }
+    }
+    Ok(multistatus(&responses))
+}
+
+async fn propfind_todo(
+    req: HttpRequest,
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let document = match parse_body(&body) {
+        Ok(document) => document,
+        Err(_) => return Ok(invalid_xml()),
+    };
+    let requested = requested(document.as_ref());
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let Some((list, todos)) = find_list(&state, user, &req)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let Some(todo) = find_resource(&todos, req.path()) else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let properties = todo_properties(&list, &todos, todo);
+    Ok(multistatus(&[response(
+        &todo_href(todo),
+        properties,
+        requested.as_deref(),
+    )]))
+}
+
+/// `calendar-query` returns all todos of the list, unless it asks for other
+/// components like events. Time ranges and other filters are left to the
+/// app. `calendar-multiget` returns the todos of the hrefs.
+async fn report(
+    req: HttpRequest,
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let document = match parse_body(&body) {
+        Ok(Some(document)) => document,
+        Ok(None) => return Ok(HttpResponse::BadRequest().body("The report is missing")),
+        Err(_) => return Ok(invalid_xml()),
+    };
+    let requested = requested(Some(&document));
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let Some((list, todos)) = find_list(&state, user, &req)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let root = document.root_element();
+    // the todo of each href, or the href which was not found
+    let resources: Vec<Result<&Todo, &str>> = if root.has_tag_name((CALDAV, "calendar-query")) {
+        let only_todos = root
+            .descendants()
+            .filter(|node| node.has_tag_name((CALDAV, "comp-filter")))
+            .all(|filter| matches!(filter.attribute("name"), Some("VCALENDAR" | "VTODO")));
+        match only_todos {
+            true => todos.iter().map(Ok).collect(),
+            false => vec![],
+        }
+    } else if root.has_tag_name((CALDAV, "calendar-multiget")) {
+        root.children()
+            .filter(|node| node.has_tag_name((DAV, "href")))
+            .map(|node| {
+                let href = node.text().unwrap_or_default().trim();
+                find_resource(&todos, href).ok_or(href)
+            })
+            .collect()
+    } else {
+        return Ok(forbidden("d:supported-report"));
+    };
+    let responses: Vec<String> = resources
+        .into_iter()
+        .map(|resource| match resource {
+            Ok(todo) => response(
+                &todo_href(todo),
+                todo_properties(&list, &todos, todo),
+                requested.as_deref(),
+            ),
+            Err(href) => format!(
+                "<d:response><d:href>{}</d:href><d:status>HTTP/1.1 404 Not Found</d:status></d:response>",
+                escape(href)
+            ),
+        })
+        .collect();
+    Ok(multistatus(&responses))
+}
+
+/// The whole list as calendar, like the calendar feed.
+async fn get_calendar(
+    req: HttpRequest,
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+) -> Result<HttpResponse, ApiError> {
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let Some((list, todos)) = find_list(&state, user, &req)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let items: Vec<&Todo> = todos.iter().collect();
+    Ok(HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .append_header((header::ETAG, ctag(&list, &todos)))
+        .body(ical::format_calendar(&list.title, &items, &[])))
+}
+
+async fn get_todo(
+    req: HttpRequest,
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+) -> Result<HttpResponse, ApiError> {
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let Some((list, todos)) = find_list(&state, user, &req)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let Some(todo) = find_resource(&todos, req.path()) else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .append_header((header::ETAG, etag(todo, parent(&todos, todo))))
+        .body(calendar_data(&list, &todos, todo)))
+}
+
+/// Creates or replaces the todo of the resource with the single `VTODO` of
+/// the body. Properties which todos do not have, like descriptions and
+/// alarms, are dropped, so no ETag is returned and the app fetches the todo
+/// again. A subtask is linked to its todo if that is already in the list.
+async fn put_todo(
+    req: HttpRequest,
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let mut state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let Some((list, todos)) = find_list(&state, user, &req)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let old = find_resource(&todos, req.path()).cloned();
+    let old_etag = old.as_ref().map(|old| etag(old, parent(&todos, old)));
+    if precondition_failed(&req, old_etag.as_deref()) {
+        return Ok(HttpResponse::PreconditionFailed().finish());
+    }
+    let mut errors = vec![];
+    let drafts = ical::parse_calendar(&body, &mut errors);
+    let draft = match (drafts.as_slice(), errors.is_empty()) {
+        ([draft], true) => draft,
+        _ => return Ok(forbidden("c:valid-calendar-data")),
+    };
+    let Some(uid) = draft.key.clone() else {
+        return Ok(forbidden("c:valid-calendar-object-resource"));
+    };
+    let taken = todos
+        .iter()
+        .any(|todo| ical::uid(todo) == uid && Some(todo.id) != old.as_ref().map(|old| old.id));
+    let renamed = old.as_ref().is_some_and(|old| ical::uid(old) != uid);
+    if taken || renamed {
+        return Ok(forbidden("c:no-uid-conflict"));
+    }
+    // subtasks cannot have subtasks
+    let has_subtasks = old
+        .as_ref()
+        .is_some_and(|old| todos.iter().any(|todo| todo.parent_id == Some(old.id)));
+    let parent_id = draft
+        .parent_key
+        .as_ref()
+        .filter(|_| !has_subtasks)
+        .and_then(|key| {
+            todos
+                .iter()
+                .find(|todo| todo.parent_id.is_none() && ical::uid(todo) == *key)
+        })
+        .map(|parent| parent.id)
+        .filter(|id| Some(*id) != old.as_ref().map(|old| old.id));
+    let tags = tags::resolve(&*state.store, user.0, draft.tags.clone())?;
+    let Some(old) = old else {
+        let todo = state.store.create(Todo {
+            list_id: list.id,
+            parent_id,
+            name: draft.name.clone(),
+            done: draft.done,
+            due: draft.due,
+            priority: draft.priority,
+            tags,
+            recurrence: draft.recurrence.clone(),
+            routine: draft.routine,
+            created: draft.created,
+            completed: draft.completed.or(draft.done.then(todo::today)),
+            uid: Some(uid),
+            resource: Some(last_segment(req.path()).to_string()),
+            ..Todo::default()
+        })?;
+        audit::created(&mut state, Some(user.0), &todo)?;
+        state.events.send(match todo.parent_id {
+            Some(_) => TodoEvent::Changed(todo.clone()),
+            None => TodoEvent::Added(todo.clone()),
+        });
+        return Ok(HttpResponse::Created().finish());
+    };
+    let mut todo = Todo {
+        parent_id,
+        name: draft.name.clone(),
+        due: draft.due,
+        priority: draft.priority,
+        tags,
+        recurrence: draft.recurrence.clone(),
+        // apps may drop the property of routines, so the stored value is kept
+        routine: old.routine,
+        ..old.clone()
+    };
+    todo.set_done(draft.done);
+    if draft.completed.is_some() {
+        todo.completed = draft.completed;
+    }
+    state.store.update(&todo)?;
+    audit::record_changes(&mut state, Some(user.0), &old, &todo)?;
+    state.events.send(match old.parent_id == todo.parent_id {
+        true => TodoEvent::Changed(todo.clone()),
+        false => TodoEvent::ListChanged(list.id),
+    });
+    if todo.done && !old.done {
+        schedule_next(&mut state, Some(user.0), &todo)?;
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Deletes the todo of the resource together with its subtasks.
+async fn delete_todo(
+    req: HttpRequest,
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+) -> Result<HttpResponse, ApiError> {
+    let mut state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let Some((_, todos)) = find_list(&state, user, &req)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let Some(todo) = find_resource(&todos, req.path()).cloned() else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    if precondition_failed(&req, Some(&etag(&todo, parent(&todos, &todo)))) {
+        return Ok(HttpResponse::PreconditionFailed().finish());
+    }
+    let removed = state.store.with_subtasks(&todo)?;
+    state.store.remove(todo.id)?;
+    for removed in &removed {
+        audit::record(&mut state, Some(user.0), removed, audit::Action::Deleted)?;
+    }
+    state.events.send(TodoEvent::Removed(todo));
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// A route for a WebDAV method like PROPFIND, which [`Method`] has no
+/// constant for.
+fn dav_method(name: &'static str) -> Route {
+    web::route().guard(guard::fn_guard(move |ctx| {
+        ctx.head().method.as_str() == name
+    }))
+}
+
+/// All CalDAV routes and the `/.well-known/caldav` redirect.
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/.well-known/caldav").to(well_known))
+        .service(
+            web::scope("/caldav")
+                .service(
+                    web::resource(["", "/"])
+                        .route(dav_method("PROPFIND").to(propfind_home))
+                        .route(web::method(Method::OPTIONS).to(options)),
+                )
+                .service(
+                    web::resource(["/{list_id}", "/{list_id}/"])
+                        .route(dav_method("PROPFIND").to(propfind_calendar))
+                        .route(dav_method("REPORT").to(report))
+                        .route(web::get().to(get_calendar))
+                        .route(web::method(Method::OPTIONS).to(options)),
+                )
+                .service(
+                    web::resource("/{list_id}/{name}")
+                        .route(dav_method("PROPFIND").to(propfind_todo))
+                        .route(web::get().to(get_todo))
+                        .route(web::put().to(put_todo))
+                        .route(web::delete().to(delete_todo))
+                        .route(web::method(Method::OPTIONS).to(options)),
+                ),
+        );
+}
@@ -0,0 +1,176 @@
+//! The daily reset, which archives the completed todos of every list once a day
+//! and records how much of the day's list was completed.
+
+use std::sync::Mutex;
+
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use chrono::{Days, Duration, Local, NaiveDate, NaiveTime};
+use maud::{html, DOCTYPE};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::{self, Action},
+    events::TodoEvent,
+    path_id,
+    store::StoreError,
+    ApiError, AppState,
+};
+
+/// The completion of a list on one day, recorded by the daily reset.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DayRecord {
+    pub list_id: u128,
+    pub date: NaiveDate,
+    /// Number of completed todos, subtasks are not counted like in the
+    /// statistic of the list.
+    pub done: usize,
+    pub total: usize,
+    /// Names of the completed todos without subtasks.
+    pub completed: Vec<String>,
+}
+
+impl DayRecord {
+    /// Share of completed todos in percent.
+    pub fn rate(&self) -> usize {
+        match self.total {
+            0 => 0,
+            total => self.done * 100 / total,
+        }
+    }
+}
+
+/// Reads the local time of the daily reset from `TODO_RESET_TIME`, e.g.
+/// `04:30`. Without it the reset runs at midnight.
+pub fn reset_time() -> std::io::Result<NaiveTime> {
+    match std::env::var("TODO_RESET_TIME") {
+        Ok(time) => NaiveTime::parse_from_str(&time, "%H:%M")
+            .map_err(|_| std::io::Error::other("TODO_RESET_TIME must look like 04:30")),
+        Err(_) => Ok(NaiveTime::MIN),
+    }
+}
+
+/// Records the completion of every list for `date`, empty lists included, then
+/// unchecks the daily routines with their subtasks and archives the other
+/// completed todos.
+pub fn reset(state: &mut AppState, date: NaiveDate) -> Result<(), StoreError> {
+    let todos = state.store.list(None)?;
+    for list_id in state.store.lists(None)?.into_iter().map(|list| list.id) {
+        let todos: Vec<_> = todos
+            .iter()
+            .filter(|todo| todo.list_id == list_id)
+            .collect();
+        let top: Vec<_> = todos
+            .iter()
+            .filter(|todo| todo.parent_id.is_none())
+            .collect();
+        let completed: Vec<String> = top
+            .iter()
+            .filter(|todo| todo.done)
+            .map(|todo| todo.name.clone())
+            .collect();
+        state.store.add_day(&DayRecord {
+            list_id,
+            date,
+            done: completed.len(),
+            total: top.len(),
+            completed,
+        })?;
+        let routines: Vec<u128> = todos
+            .iter()
+            .filter(|todo| todo.routine)
+            .map(|todo| todo.id)
+            .collect();
+        for todo in &todos {
+            let routine = todo.routine || todo.parent_id.is_some_and(|id| routines.contains(&id));
+            if todo.done && routine {
+                let mut reopened = (*todo).clone();
+                reopened.set_done(false);
+                state.store.update(&reopened)?;
+                audit::record_changes(state, None, todo, &reopened)?;
+            }
+        }
+        for todo in state.store.archive_done(list_id)? {
+            audit::record(state, None, &todo, Action::Archived)?;
+        }
+        state.events.send(TodoEvent::ListChanged(list_id));
+    }
+    Ok(())
+}
+
+/// Runs the reset every day at the given local time. A day is recorded with
+/// the date of its middle, so a reset at 04:30 records the day before and a
+/// reset at 23:00 the same day.
+pub async fn run(data: web::Data<Mutex<AppState>>, at: NaiveTime) {
+    loop {
+        let now = Local::now().naive_local();
+        let mut next = now.date().and_time(at);
+        if next <= now {
+            next = next + Days::new(1);
+        }
+        let wait = (next - now).to_std().unwrap_or_default();
+        actix_web::rt::time::sleep(wait).await;
+        let Ok(mut state) = data.lock() else {
+            eprintln!("daily reset: mutex lock failed");
+            continue;
+        };
+        let date = (next - Duration::hours(12)).date();
+        if let Err(err) = reset(&mut state, date) {
+            eprintln!("daily reset failed: {}", err);
+        }
+    }
+}
+
+#[get("/history")]
+async fn history(
+    req: HttpRequest,
+    data: web::Data<Mutex<AppState>>,
+) -> Result<HttpResponse, ApiError> {
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let Some(list) = state.store.get_list(path_id(&req, "list_id")?)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let days = state.store.days(list.id)?;
+    let body = html! {
+        (DOCTYPE)
+        script src="/assets/tailwind.min.js" {}
+        link rel="icon" type="image/png" href="/assets/favicon.png";
+        link src="/assets/global.css" rel="stylesheet" {}
+        title { "History - " (list.title) " - Todo" }
+
+        body ."min-h-sreen" .text-white .bg-black ."p-4" {
+            main class="container m-auto max-w-2xl flex flex-col gap-4" {
+                a href=(list.url()) class="text-sm text-neutral-400" { "← " (list.title) }
+                h1 class="text-2xl" { "History" }
+                @if days.is_empty() {
+                    p class="text-neutral-400" {
+                        "Nothing recorded yet. Every day the completed todos are archived and the completion of the day is added here."
+                    }
+                }
+                @for day in &days {
+                    details class="flex flex-col gap-1" {
+                        summary class="flex flex-row gap-4 items-center cursor-pointer" {
+                            span class="w-28" { (day.date.format("%a %Y-%m-%d")) }
+                            div class="flex-1 h-2 rounded bg-neutral-800" {
+                                div class="h-2 rounded bg-blue-500" style=(format!("width: {}%", day.rate())) {}
+                            }
+                            span class="w-32 text-sm text-neutral-400 text-right" {
+                                (day.done) " of " (day.total) " (" (day.rate()) "%)"
+                            }
+                        }
+                        ul class="ml-32 text-sm text-neutral-400 list-disc" {
+                            @for name in &day.completed {
+                                li { (name) }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body.into_string()))
+}
@@ -0,0 +1,149 @@
+//! Live updates: the mutation handlers publish events, which are sent to all
+//! open pages of the list as server-sent events.
+
+use std::{sync::Mutex, time::Duration};
+
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use futures_util::stream;
+use maud::html;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    path_id,
+    store::{StoreError, TodoStore},
+    todo::{self, Todo},
+    ApiError, AppState,
+};
+
+/// Number of events a slow client may fall behind before it gets a reload.
+const CAPACITY: usize = 64;
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone)]
+pub enum TodoEvent {
+    Added(Todo),
+    /// The todo was toggled or edited.
+    Changed(Todo),
+    Removed(Todo),
+    /// Several todos of the list changed at once, e.g. archiving completed ones.
+    ListChanged(u128),
+}
+
+impl TodoEvent {
+    fn list_id(&self) -> u128 {
+        match self {
+            TodoEvent::Added(todo) | TodoEvent::Changed(todo) | TodoEvent::Removed(todo) => {
+                todo.list_id
+            }
+            TodoEvent::ListChanged(list_id) => *list_id,
+        }
+    }
+
+    /// Encodes the event for the page. `patch` events carry out-of-band swaps
+    /// for single items, `reload` events make the page fetch the whole list.
+    /// Added todos reload the list, so the tab which added the todo does not
+    /// show it twice. Changed items are rendered from the store, because a
+    /// parent shows the current state of its subtasks.
+    fn to_sse(&self, store: &dyn TodoStore) -> Result<String, StoreError> {
+        let (name, data) = match self {
+            TodoEvent::Removed(todo) if todo.parent_id.is_none() => {
+                ("patch", todo.render_oob(&[], "delete").into_string())
+            }
+            TodoEvent::Changed(todo) | TodoEvent::Removed(todo) => {
+                let todos = store.list(Some(todo.list_id))?;
+                match todo::render_top(&todos, todo, Some("true")) {
+                    Some(markup) => ("patch", markup.into_string()),
+                    None => ("reload", String::new()),
+                }
+            }
+            TodoEvent::Added(_) | TodoEvent::ListChanged(_) => ("reload", String::new()),
+        };
+        Ok(message(name, &data))
+    }
+}
+
+fn message(name: &str, data: &str) -> String {
+    let mut message = format!("event: {}\n", name);
+    for line in data.split('\n') {
+        message.push_str("data: ");
+        message.push_str(line);
+        message.push('\n');
+    }
+    message.push('\n');
+    message
+}
+
+/// Sends the events to all subscribers. Sending never blocks and without
+/// subscribers the events are dropped.
+pub struct Broadcaster {
+    sender: broadcast::Sender<TodoEvent>,
+}
+
+impl Default for Broadcaster {
+    fn default() -> Self {
+        Broadcaster {
+            sender: broadcast::channel(CAPACITY).0,
+        }
+    }
+}
+
+impl Broadcaster {
+    pub fn send(&self, event: TodoEvent) {
+        // an error only means that nobody listens
+        let _ = self.sender.send(event);
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<TodoEvent> {
+        self.sender.subscribe()
+    }
+}
+
+/// Stream of the events of one list. Connect with `sse-connect` of the htmx
+/// SSE extension.
+#[get("/events")]
+async fn stream_events(
+    req: HttpRequest,
+    data: web::Data<Mutex<AppState>>,
+) -> Result<HttpResponse, ApiError> {
+    let list_id = path_id(&req, "list_id")?;
+    let receiver = match data.lock() {
+        Ok(state) => state.events.subscribe(),
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let events = stream::unfold(receiver, move |mut receiver| {
+        let data = data.clone();
+        async move {
+            loop {
+                let message = match tokio::time::timeout(KEEP_ALIVE, receiver.recv()).await {
+                    Ok(Ok(event)) if event.list_id() == list_id => match data.lock() {
+                        Ok(state) => event
+                            .to_sse(&*state.store)
+                            .unwrap_or_else(|_| message("reload", "")),
+                        Err(_) => message("reload", ""),
+                    },
+                    Ok(Ok(_)) => continue,
+                    // missed events are replaced by reloading the whole list
+                    Ok(Err(RecvError::Lagged(_))) => message("reload", ""),
+                    Ok(Err(RecvError::Closed)) => return None,
+                    // comments keep the connection open
+                    Err(_) => ": keep-alive\n\n".to_string(),
+                };
+                return Some((
+                    Ok::<_, actix_web::Error>(web::Bytes::from(message)),
+                    receiver,
+                ));
+            }
+        }
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .append_header(("Cache-Control", "no-cache"))
+        .streaming(events))
+}
+
+/// Hidden element which applies the `patch` events.
+pub fn render_listener() -> maud::Markup {
+    html! {
+        div sse-swap="patch" class="hidden" {}
+    }
+}
@@ -0,0 +1,339 @@
+//! Download of the todos as JSON, CSV, a Markdown checklist, todo.txt or
+//! iCalendar, and the iCalendar feed for calendar apps.
+
+use std::{iter, sync::Mutex};
+
+use actix_web::{get, web, HttpResponse};
+use futures_util::{stream, Stream};
+use serde::Deserialize;
+
+use crate::{
+    auth::CurrentUser,
+    ical, owns_list, query_id,
+    todo::{self, Priority, Todo, TodoList},
+    todotxt, ApiError, AppState,
+};
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Json,
+    Csv,
+    #[serde(alias = "markdown")]
+    Md,
+    #[serde(alias = "todotxt")]
+    Txt,
+    #[serde(alias = "ical")]
+    Ics,
+}
+
+impl Format {
+    pub const ALL: [Format; 5] = [
+        Format::Json,
+        Format::Csv,
+        Format::Md,
+        Format::Txt,
+        Format::Ics,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Csv => "csv",
+            Format::Md => "md",
+            Format::Txt => "txt",
+            Format::Ics => "ics",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Format> {
+        Format::ALL
+            .into_iter()
+            .find(|format| format.as_str() == value)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Format::Json => "JSON",
+            Format::Csv => "CSV",
+            Format::Md => "Markdown",
+            Format::Txt => "todo.txt",
+            Format::Ics => "iCalendar",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Md => "text/markdown; charset=utf-8",
+            Format::Txt => "text/plain; charset=utf-8",
+            Format::Ics => "text/calendar; charset=utf-8",
+        }
+    }
+}
+
+/// The columns of the CSV export. `list` is the title of the list.
+pub const CSV_COLUMNS: [&str; 15] = [
+    "id",
+    "list_id",
+    "list",
+    "parent_id",
+    "name",
+    "done",
+    "due",
+    "priority",
+    "tags",
+    "contexts",
+    "recurrence",
+    "routine",
+    "position",
+    "created",
+    "completed",
+];
+
+/// Quotes the field if it contains a separator, a quote or a line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// The row of the todo, tags and contexts are separated by spaces.
+fn csv_row(list: &TodoList, todo: &Todo) -> String {
+    let tags: Vec<&str> = todo.tags.iter().map(|tag| tag.name.as_str()).collect();
+    let row = [
+        todo.id.to_string(),
+        list.id.to_string(),
+        list.title.clone(),
+        todo.parent_id.map(|id| id.to_string()).unwrap_or_default(),
+        todo.name.clone(),
+        todo.done.to_string(),
+        todo.due.map(|due| due.to_string()).unwrap_or_default(),
+        todo.priority.as_str().to_string(),
+        tags.join(" "),
+        todo.contexts.join(" "),
+        todo.recurrence
+            .as_ref()
+            .map(|recurrence| recurrence.to_string())
+            .unwrap_or_default(),
+        todo.routine.to_string(),
+        todo.position.to_string(),
+        todo.created
+            .map(|date| date.to_string())
+            .unwrap_or_default(),
+        todo.completed
+            .map(|date| date.to_string())
+            .unwrap_or_default(),
+    ];
+    let row: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+    format!("{}\r\n", row.join(","))
+}
+
+/// A checklist item like `- [x] Buy milk #shopping (due 2024-12-31, high priority)`.
+fn md_item(todo: &Todo, indent: &str) -> String {
+    let mut line = format!(
+        "{}- [{}] {}",
+        indent,
+        if todo.done { "x" } else { " " },
+        todo.name
+    );
+    for tag in &todo.tags {
+        line.push_str(" #");
+        line.push_str(&tag.name);
+    }
+    let mut details = vec![];
+    if let Some(due) = todo.due {
+        details.push(format!("due {}", due));
+    }
+    if todo.priority != Priority::Normal {
+        details.push(format!("{} priority", todo.priority.as_str()));
+    }
+    if let Some(recurrence) = &todo.recurrence {
+        details.push(format!("repeats {}", recurrence));
+    }
+    if !details.is_empty() {
+        line.push_str(&format!(" ({})", details.join(", ")));
+    }
+    line.push('\n');
+    line
+}
+
+/// The todo in the format, `todos` are the todos of its list. Subtasks of the
+/// Markdown checklist are indented below their todo, so they are written
+/// together with it.
+fn format_row(
+    format: Format,
+    list: &TodoList,
+    todos: &[Todo],
+    todo: &Todo,
+    stamp: &str,
+) -> Result<String, ApiError> {
+    let row = match format {
+        Format::Json => {
+            let json = serde_json::to_string_pretty(todo).map_err(|_| ApiError { name: "json" })?;
+            format!("\n  {}", json.replace('\n', "\n  "))
+        }
+        Format::Csv => csv_row(list, todo),
+        Format::Md if todo.parent_id.is_some() => String::new(),
+        Format::Md => {
+            let mut md = md_item(todo, "");
+            for subtask in todo::subtasks(todos, todo.id) {
+                md.push_str(&md_item(&subtask, "  "));
+            }
+            md
+        }
+        // todos with subtasks get their id as `id:`
+        Format::Txt => {
+            let parent = todos.iter().any(|item| item.parent_id == Some(todo.id));
+            format!(
+                "{}\n",
+                todotxt::format_line(todo, parent.then_some(todo.id))
+            )
+        }
+        Format::Ics => {
+            let parent = todos
+                .iter()
+                .find(|parent| Some(parent.id) == todo.parent_id);
+            ical::format_todo(todo, parent, stamp)
+        }
+    };
+    Ok(row)
+}
+
+/// The export in pieces, which are written while the response is sent: the
+/// head, a row per todo and the end. The Markdown checklist has a heading per
+/// list, the calendar is named after the list, or "Todos" for all lists.
+fn rows(format: Format, lists: Lists) -> impl Iterator<Item = Result<String, ApiError>> {
+    let head = match (format, lists.as_slice()) {
+        (Format::Json, _) => "[".to_string(),
+        (Format::Csv, _) => format!("{}\r\n", CSV_COLUMNS.join(",")),
+        (Format::Ics, [(list, _)]) => ical::calendar_head(&list.title),
+        (Format::Ics, _) => ical::calendar_head("Todos"),
+        (Format::Md | Format::Txt, _) => String::new(),
+    };
+    let end = match format {
+        Format::Json => "\n]",
+        Format::Ics => ical::CALENDAR_END,
+        Format::Csv | Format::Md | Format::Txt => "",
+    };
+    let stamp = ical::stamp();
+    let body = lists
+        .into_iter()
+        .enumerate()
+        .flat_map(move |(index, (list, todos))| {
+            let heading = match (format, index) {
+                (Format::Md, 0) => Some(format!("# {}\n\n", list.title)),
+                (Format::Md, _) => Some(format!("\n# {}\n\n", list.title)),
+                _ => None,
+            };
+            let stamp = stamp.clone();
+            let rows = (0..todos.len())
+                .map(move |row| format_row(format, &list, &todos, &todos[row], &stamp));
+            heading.map(Ok).into_iter().chain(rows)
+        })
+        .enumerate()
+        // JSON rows are separated by commas
+        .map(move |(index, row)| match row {
+            Ok(row) if format == Format::Json && index > 0 => Ok(format!(",{}", row)),
+            row => row,
+        });
+    iter::once(Ok(head))
+        .chain(body)
+        .chain(iter::once(Ok(end.to_string())))
+}
+
+/// The response body of the rows.
+fn stream_rows(format: Format, lists: Lists) -> impl Stream<Item = Result<web::Bytes, ApiError>> {
+    stream::iter(rows(format, lists).map(|row| row.map(web::Bytes::from)))
+}
+
+/// The lists with their todos.
+type Lists = Vec<(TodoList, Vec<Todo>)>;
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: Format,
+    /// Exports only this list instead of all lists of the user.
+    #[serde(default, deserialize_with = "query_id")]
+    list_id: Option<u128>,
+}
+
+/// Loads the lists of the user with their todos in their manual order, or only
+/// the list with the id. `None` if the list does not belong to the user.
+fn load(
+    state: &AppState,
+    user: CurrentUser,
+    list_id: Option<u128>,
+) -> Result<Option<Lists>, ApiError> {
+    let lists = match list_id {
+        Some(list_id) if owns_list(&*state.store, user, list_id)? => {
+            state.store.get_list(list_id)?.into_iter().collect()
+        }
+        Some(_) => return Ok(None),
+        None => state.store.lists(Some(user.0))?,
+    };
+    let mut todos = vec![];
+    for list in lists {
+        let items = state.store.list(Some(list.id))?;
+        todos.push((list, items));
+    }
+    Ok(Some(todos))
+}
+
+/// Downloads the todos of the user in their manual order. Archived todos are
+/// left out.
+#[get("/export")]
+async fn export(
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let Some(todos) = load(&state, user, query.list_id)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    // the rows are written from the loaded copy, other requests can go on
+    drop(state);
+    Ok(HttpResponse::Ok()
+        .content_type(query.format.content_type())
+        .append_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"todos.{}\"", query.format.as_str()),
+        ))
+        .streaming(stream_rows(query.format, todos)))
+}
+
+#[derive(Deserialize)]
+struct FeedQuery {
+    #[serde(default, deserialize_with = "query_id")]
+    list_id: Option<u128>,
+}
+
+/// The todos as iCalendar feed at a stable URL, which calendar apps can
+/// subscribe to with basic auth. `?list_id=` limits it to one list.
+#[get("/calendar.ics")]
+async fn feed(
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+    query: web::Query<FeedQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let Some(todos) = load(&state, user, query.list_id)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    drop(state);
+    Ok(HttpResponse::Ok()
+        .content_type(Format::Ics.content_type())
+        .streaming(stream_rows(Format::Ics, todos)))
+}
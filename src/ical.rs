@@ -0,0 +1,606 @@
+//! The iCalendar format (RFC 5545): todos as `VTODO` components, e.g.
+//! `SUMMARY:Buy milk`, `STATUS:COMPLETED` and `DUE;VALUE=DATE:20241231`.
+//!
+//! The export, the calendar feed and CalDAV write one `VTODO` per todo, the
+//! import and CalDAV read the `VTODO` components of a calendar and skip events
+//! and the other components. Subtasks refer to the `UID` of their todo with `RELATED-TO`.
+
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime, TimeZone, Utc, Weekday};
+
+use crate::{
+    import::{Draft, LineError},
+    todo::{self, Priority, Recurrence, Todo},
+};
+
+/// The property which marks daily routines, iCalendar has none for them.
+const ROUTINE: &str = "X-HTMX-RUST-ROUTINE";
+
+/// Lines longer than this many bytes are folded.
+const MAX_LINE_LENGTH: usize = 75;
+
+const WEEKDAYS: [Weekday; 5] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+];
+
+/// The `UID` of the todo, the one of the CalDAV client or else one made from
+/// the id.
+pub fn uid(todo: &Todo) -> String {
+    match &todo.uid {
+        Some(uid) => uid.clone(),
+        None => format!("todo-{}@htmx-rust", todo.id),
+    }
+}
+
+/// Escapes the separators and line breaks of a text value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+fn unescape(value: &str) -> String {
+    let mut text = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n' | 'N') => text.push('\n'),
+                Some(c) => text.push(c),
+                None => {}
+            },
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+/// Splits a list value like `CATEGORIES` at the commas which are not escaped.
+fn split_list(value: &str) -> Vec<String> {
+    let mut items = vec![];
+    let mut start = 0;
+    let mut escaped = false;
+    for (index, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ',' => {
+                items.push(unescape(&value[start..index]));
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(unescape(&value[start..]));
+    items
+}
+
+/// Appends the line, folded after 75 bytes, with the line break of iCalendar.
+fn push_line(ics: &mut String, line: &str) {
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > MAX_LINE_LENGTH {
+            ics.push_str("\r\n ");
+            length = 1;
+        }
+        ics.push(c);
+        length += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+
+/// Joins folded lines. Returns the lines with the line they start on.
+fn unfold(content: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = vec![];
+    for (index, text) in content.lines().enumerate() {
+        match (text.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some((_, line))) => line.push_str(rest),
+            _ if text.trim().is_empty() => {}
+            _ => lines.push((index + 1, text.to_string())),
+        }
+    }
+    lines
+}
+
+/// A content line like `DUE;VALUE=DATE:20241231`.
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn parse(line: &str) -> Option<Property> {
+        // parameter values in quotes may contain colons
+        let mut quoted = false;
+        let (colon, _) = line.char_indices().find(|&(_, c)| {
+            if c == '"' {
+                quoted = !quoted;
+            }
+            c == ':' && !quoted
+        })?;
+        let mut parts = line[..colon].split(';');
+        let name = parts.next()?.trim().to_uppercase();
+        if name.is_empty() {
+            return None;
+        }
+        let params = parts
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| (key.to_uppercase(), value.trim_matches('"').to_string()))
+            .collect();
+        Some(Property {
+            name,
+            params,
+            value: line[colon + 1..].to_string(),
+        })
+    }
+
+    fn param(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Midnight of the day in local time, as UTC date-time like `20241230T230000Z`.
+fn format_date_time(date: NaiveDate) -> String {
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap_or_default();
+    let time = match Local.from_local_datetime(&midnight).earliest() {
+        Some(time) => time.with_timezone(&Utc),
+        None => midnight.and_utc(),
+    };
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Reads a date or the date of a date-time. UTC date-times are converted to
+/// local time, other date-times are taken as they are.
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    let value = value.trim();
+    if let Some(time) = value.strip_suffix(['Z', 'z']) {
+        let time = NaiveDateTime::parse_from_str(time, "%Y%m%dT%H%M%S").ok()?;
+        return Some(time.and_utc().with_timezone(&Local).date_naive());
+    }
+    NaiveDate::parse_from_str(value.get(..8)?, "%Y%m%d").ok()
+}
+
+/// Priorities 1 to 9, 1 is the highest. Normal todos have none.
+fn format_priority(priority: Priority) -> Option<u8> {
+    match priority {
+        Priority::Urgent => Some(1),
+        Priority::High => Some(3),
+        Priority::Normal => None,
+        Priority::Low => Some(9),
+    }
+}
+
+fn parse_priority(value: &str) -> Result<Priority, &'static str> {
+    match value.trim().parse::<u8>() {
+        Ok(0 | 5) => Ok(Priority::Normal),
+        Ok(1) => Ok(Priority::Urgent),
+        Ok(2..=4) => Ok(Priority::High),
+        Ok(6..=9) => Ok(Priority::Low),
+        _ => Err("The priority must be a number from 0 to 9"),
+    }
+}
+
+fn weekday_code(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+fn format_rrule(recurrence: &Recurrence) -> String {
+    let by_day = |days: &[Weekday]| {
+        days.iter()
+            .map(|day| weekday_code(*day))
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    match recurrence {
+        Recurrence::Daily => "FREQ=DAILY".to_string(),
+        Recurrence::Weekdays => format!("FREQ=WEEKLY;BYDAY={}", by_day(&WEEKDAYS)),
+        Recurrence::Weekly(days) => format!("FREQ=WEEKLY;BYDAY={}", by_day(days)),
+        Recurrence::Monthly(day) => format!("FREQ=MONTHLY;BYMONTHDAY={}", day),
+        Recurrence::Days(days) => format!("FREQ=DAILY;INTERVAL={}", days),
+    }
+}
+
+/// Reads the rules which this app can repeat: daily, weekly on some days and
+/// monthly on a day, each with an interval of days or weeks. Weekly and
+/// monthly rules without days start from `start`. `COUNT` and `UNTIL` are
+/// ignored, the todo repeats forever.
+fn parse_rrule(value: &str, start: NaiveDate) -> Result<Recurrence, &'static str> {
+    const UNSUPPORTED: &str =
+        "The repeat rule is not supported, only daily, weekly and monthly rules can be imported";
+    let value = value.trim().to_uppercase();
+    let mut frequency = "";
+    let mut interval: u32 = 1;
+    let mut days = vec![];
+    let mut month_day = None;
+    for part in value.split(';') {
+        match part.split_once('=') {
+            Some(("FREQ", value)) => frequency = value,
+            Some(("INTERVAL", value)) => interval = value.parse().map_err(|_| UNSUPPORTED)?,
+            Some(("BYDAY", value)) => {
+                for code in value.split(',') {
+                    let weekday = WEEKDAYS
+                        .into_iter()
+                        .chain([Weekday::Sat, Weekday::Sun])
+                        .find(|weekday| weekday_code(*weekday) == code)
+                        .ok_or(UNSUPPORTED)?;
+                    days.push(weekday);
+                }
+            }
+            Some(("BYMONTHDAY", value)) => {
+                month_day = Some(value.parse().map_err(|_| UNSUPPORTED)?)
+            }
+            Some(("COUNT" | "UNTIL" | "WKST", _)) => {}
+            _ => return Err(UNSUPPORTED),
+        }
+    }
+    days.sort_by_key(Weekday::num_days_from_monday);
+    days.dedup();
+    let recurrence = match (frequency, interval, days.is_empty(), month_day) {
+        ("DAILY", 1, true, None) => Recurrence::Daily,
+        ("DAILY", days @ 2..=366, true, None) => Recurrence::Days(days),
+        ("WEEKLY", 1, false, None) if days == WEEKDAYS => Recurrence::Weekdays,
+        ("WEEKLY", 1, false, None) => Recurrence::Weekly(days),
+        ("WEEKLY", 1, true, None) => Recurrence::Weekly(vec![start.weekday()]),
+        ("WEEKLY", weeks @ 2..=52, true, None) => Recurrence::Days(weeks * 7),
+        ("MONTHLY", 1, true, Some(day @ 1..=31)) => Recurrence::Monthly(day),
+        ("MONTHLY", 1, true, None) => Recurrence::Monthly(start.day()),
+        _ => return Err(UNSUPPORTED),
+    };
+    Ok(recurrence)
+}
+
+/// Appends the todo as `VTODO`. `stamp` is the time of the export.
+fn push_todo(ics: &mut String, todo: &Todo, parent: Option<&Todo>, stamp: &str) {
+    push_line(ics, "BEGIN:VTODO");
+    push_line(ics, &format!("UID:{}", uid(todo)));
+    push_line(ics, &format!("DTSTAMP:{}", stamp));
+    if let Some(created) = todo.created {
+        push_line(ics, &format!("CREATED:{}", format_date_time(created)));
+    }
+    push_line(ics, &format!("SUMMARY:{}", escape(&todo.name)));
+    if todo.done {
+        push_line(ics, "STATUS:COMPLETED");
+        if let Some(completed) = todo.completed {
+            push_line(ics, &format!("COMPLETED:{}", format_date_time(completed)));
+        }
+    } else {
+        push_line(ics, "STATUS:NEEDS-ACTION");
+    }
+    if let Some(due) = todo.due {
+        let due = due.format("%Y%m%d");
+        // repeat rules count from the start
+        if todo.recurrence.is_some() {
+            push_line(ics, &format!("DTSTART;VALUE=DATE:{}", due));
+        }
+        push_line(ics, &format!("DUE;VALUE=DATE:{}", due));
+    }
+    if let Some(priority) = format_priority(todo.priority) {
+        push_line(ics, &format!("PRIORITY:{}", priority));
+    }
+    if !todo.tags.is_empty() {
+        let tags: Vec<String> = todo.tags.iter().map(|tag| escape(&tag.name)).collect();
+        push_line(ics, &format!("CATEGORIES:{}", tags.join(",")));
+    }
+    if let Some(recurrence) = &todo.recurrence {
+        push_line(ics, &format!("RRULE:{}", format_rrule(recurrence)));
+    }
+    if todo.routine {
+        push_line(ics, &format!("{}:TRUE", ROUTINE));
+    }
+    if let Some(parent) = parent {
+        push_line(ics, &format!("RELATED-TO;RELTYPE=PARENT:{}", uid(parent)));
+    }
+    push_line(ics, "END:VTODO");
+}
+
+/// The time of an export as used for `DTSTAMP`.
+pub fn stamp() -> String {
+    Utc::now().format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// The lines before the todos of a calendar, `name` is shown by calendar apps.
+pub fn calendar_head(name: &str) -> String {
+    let mut ics = String::new();
+    push_line(&mut ics, "BEGIN:VCALENDAR");
+    push_line(&mut ics, "VERSION:2.0");
+    push_line(&mut ics, "PRODID:-//htmx-rust//Todos//EN");
+    push_line(&mut ics, &format!("X-WR-CALNAME:{}", escape(name)));
+    ics
+}
+
+/// The line after the todos of a calendar.
+pub const CALENDAR_END: &str = "END:VCALENDAR\r\n";
+
+/// The todo as `VTODO`, a subtask refers to the `UID` of `parent`.
+pub fn format_todo(todo: &Todo, parent: Option<&Todo>, stamp: &str) -> String {
+    let mut ics = String::new();
+    push_todo(&mut ics, todo, parent, stamp);
+    ics
+}
+
+/// A calendar with the todos, `name` is shown by calendar apps. Subtasks refer
+/// to the `UID` of their todo, which is looked up in `todos` and `parents`.
+pub fn format_calendar(name: &str, todos: &[&Todo], parents: &[&Todo]) -> String {
+    let stamp = stamp();
+    let mut ics = calendar_head(name);
+    for todo in todos {
+        let parent = todos
+            .iter()
+            .chain(parents)
+            .find(|parent| Some(parent.id) == todo.parent_id);
+        push_todo(&mut ics, todo, parent.copied(), &stamp);
+    }
+    ics.push_str(CALENDAR_END);
+    ics
+}
+
+/// Builds the draft of a `VTODO` from its properties. A todo without `STATUS`
+/// is done if it has a completion date.
+fn parse_todo(line: usize, properties: &[Property]) -> Result<Draft, &'static str> {
+    let mut draft = Draft {
+        line,
+        ..Draft::default()
+    };
+    let mut status = None;
+    let mut start = None;
+    let mut rule = None;
+    let mut tags = vec![];
+    for property in properties {
+        let value = property.value.as_str();
+        match property.name.as_str() {
+            "UID" => draft.key = Some(value.trim().to_string()),
+            "SUMMARY" => draft.name = unescape(value).replace(['\r', '\n'], " "),
+            "STATUS" => status = Some(value.trim().to_uppercase()),
+            "COMPLETED" => draft.completed = parse_date(value),
+            "CREATED" => draft.created = parse_date(value),
+            "DTSTART" => start = parse_date(value),
+            "DUE" => draft.due = Some(parse_date(value).ok_or("The due date is invalid")?),
+            "PRIORITY" => draft.priority = parse_priority(value)?,
+            "CATEGORIES" => tags.extend(split_list(value)),
+            "RRULE" => rule = Some(value),
+            ROUTINE => draft.routine = value.trim().eq_ignore_ascii_case("TRUE"),
+            "RELATED-TO"
+                if property
+                    .param("RELTYPE")
+                    .is_none_or(|kind| kind.eq_ignore_ascii_case("PARENT")) =>
+            {
+                draft.parent_key = Some(value.trim().to_string())
+            }
+            _ => {}
+        }
+    }
+    draft.done = match status {
+        Some(status) => status == "COMPLETED",
+        None => draft.completed.is_some(),
+    };
+    if !draft.done {
+        draft.completed = None;
+    }
+    // tags cannot contain spaces, categories often do
+    let tags: Vec<String> = tags
+        .iter()
+        .map(|tag| tag.split_whitespace().collect::<Vec<_>>().join("-"))
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    draft.tags = todo::validate_tags(tags.iter().map(String::as_str))?;
+    if let Some(rule) = rule {
+        let start = start.or(draft.due).unwrap_or_else(todo::today);
+        draft.recurrence = Some(parse_rrule(rule, start)?);
+    }
+    draft.name = todo::validate_name(&draft.name)?;
+    Ok(draft)
+}
+
+/// Reads the `VTODO` components of the calendar. Errors are reported at the
+/// line of their `BEGIN:VTODO`, components inside a todo like alarms are
+/// skipped.
+pub fn parse_calendar(content: &str, errors: &mut Vec<LineError>) -> Vec<Draft> {
+    const UNCLOSED: &str = "The todo is not closed with END:VTODO";
+    let lines = unfold(content);
+    if !lines
+        .iter()
+        .any(|(_, line)| line.trim().eq_ignore_ascii_case("BEGIN:VCALENDAR"))
+    {
+        errors.push(LineError::new(
+            1,
+            "The file is no iCalendar file with BEGIN:VCALENDAR",
+        ));
+        return vec![];
+    }
+    let mut drafts = vec![];
+    // the line of the open todo, its properties and the depth of nested components
+    let mut todo: Option<(usize, Vec<Property>, usize)> = None;
+    for (line, text) in lines {
+        let property = Property::parse(&text);
+        // `true` for the begin of a component, `false` for its end
+        let component = property
+            .as_ref()
+            .and_then(|property| match property.name.as_str() {
+                "BEGIN" => Some((true, property.value.trim().to_uppercase())),
+                "END" => Some((false, property.value.trim().to_uppercase())),
+                _ => None,
+            });
+        match (&mut todo, component) {
+            (None, Some((true, name))) if name == "VTODO" => todo = Some((line, vec![], 0)),
+            (None, _) => {}
+            (Some((_, _, depth)), Some((true, _))) => *depth += 1,
+            (Some((_, _, depth @ 1..)), Some((false, _))) => *depth -= 1,
+            (Some((start, properties, 0)), Some((false, name))) => {
+                match parse_todo(*start, properties) {
+                    Ok(_) if name != "VTODO" => errors.push(LineError::new(*start, UNCLOSED)),
+                    Ok(draft) => drafts.push(draft),
+                    Err(message) => errors.push(LineError::new(*start, message)),
+                }
+                todo = None;
+            }
+            (Some(_), _) if property.is_none() => errors.push(LineError::new(
+                line,
+                "Expected a property like SUMMARY:Buy milk",
+            )),
+            (Some((_, properties, 0)), _) => properties.extend(property),
+            (Some(_), _) => {}
+        }
+    }
+    if let Some((line, _, _)) = todo {
+        errors.push(LineError::new(line, UNCLOSED));
+    }
+    drafts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> Vec<Draft> {
+        let mut errors = vec![];
+        let drafts = parse_calendar(content, &mut errors);
+        let messages: Vec<&str> = errors.iter().map(|error| error.message.as_str()).collect();
+        assert!(messages.is_empty(), "{:?}", messages);
+        drafts
+    }
+
+    #[test]
+    fn folding() {
+        let line = format!("SUMMARY:{}", "Grüße an alle, ".repeat(12));
+        let mut ics = String::new();
+        push_line(&mut ics, &line);
+        let lines: Vec<&str> = ics.strip_suffix("\r\n").unwrap().split("\r\n").collect();
+        assert!(lines.len() > 2);
+        for (index, folded) in lines.iter().enumerate() {
+            assert!(folded.len() <= MAX_LINE_LENGTH, "{}", folded);
+            assert_eq!(folded.starts_with(' '), index > 0);
+        }
+        assert_eq!(unfold(&ics), vec![(1, line)]);
+        // folded lines may also continue with a tab and use plain line breaks
+        let content = "BEGIN:VCALENDAR\nBEGIN:VTODO\nSUMMARY:Buy\n  milk and\n\t eggs\nEND:VTODO\nEND:VCALENDAR\n";
+        assert_eq!(parse(content)[0].name, "Buy milk and eggs");
+    }
+
+    #[test]
+    fn escaping() {
+        let text = "a,b;c\\d\ne";
+        assert_eq!(escape(text), r"a\,b\;c\\d\ne");
+        assert_eq!(unescape(&escape(text)), text);
+        assert_eq!(unescape(r"line\Nbreak\:"), "line\nbreak:");
+        assert_eq!(split_list(r"a\,b,c\;,d"), vec!["a,b", "c;", "d"]);
+        let content = "BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\nSUMMARY:Milk\\, eggs\\; bread\\nand \\\\ more\r\nCATEGORIES:shop,errands\\,town\r\nEND:VTODO\r\nEND:VCALENDAR\r\n";
+        let mut errors = vec![];
+        parse_calendar(content, &mut errors);
+        // the comma makes the second category an invalid tag
+        assert_eq!(errors.len(), 1);
+        let content = content.replace("\\,town", " in town");
+        let draft = &parse(&content)[0];
+        assert_eq!(draft.name, "Milk, eggs; bread and \\ more");
+        assert_eq!(draft.tags, vec!["shop", "errands-in-town"]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let parent = Todo {
+            id: 1,
+            name: format!("Milk, eggs; {} \\ ünd mehr", "a long name ".repeat(8)),
+            done: true,
+            due: NaiveDate::from_ymd_opt(2024, 12, 31),
+            priority: Priority::Urgent,
+            recurrence: Some(Recurrence::Weekly(vec![Weekday::Mon, Weekday::Fri])),
+            routine: true,
+            created: NaiveDate::from_ymd_opt(2024, 12, 1),
+            completed: NaiveDate::from_ymd_opt(2024, 12, 24),
+            ..Todo::default()
+        };
+        let subtask = Todo {
+            id: 2,
+            parent_id: Some(1),
+            name: "Oat milk".to_string(),
+            priority: Priority::Low,
+            uid: Some("abc-123".to_string()),
+            ..Todo::default()
+        };
+        let ics = format_calendar("Shopping", &[&parent, &subtask], &[]);
+        let drafts = parse(&ics);
+        assert_eq!(drafts.len(), 2);
+        let draft = &drafts[0];
+        assert_eq!(draft.name, parent.name);
+        assert!(draft.done && draft.routine);
+        assert_eq!(draft.due, parent.due);
+        assert_eq!(draft.priority, Priority::Urgent);
+        assert_eq!(draft.recurrence, parent.recurrence);
+        assert_eq!(draft.created, parent.created);
+        assert_eq!(draft.completed, parent.completed);
+        assert_eq!(draft.key.as_deref(), Some("todo-1@htmx-rust"));
+        assert_eq!(drafts[1].key.as_deref(), Some("abc-123"));
+        assert_eq!(drafts[1].parent_key, draft.key);
+        assert_eq!(drafts[1].priority, Priority::Low);
+    }
+
+    #[test]
+    fn recurrences() {
+        let start = NaiveDate::from_ymd_opt(2024, 12, 31).unwrap();
+        for recurrence in [
+            Recurrence::Daily,
+            Recurrence::Weekdays,
+            Recurrence::Weekly(vec![Weekday::Mon, Weekday::Fri]),
+            Recurrence::Weekly(vec![Weekday::Sun]),
+            Recurrence::Monthly(1),
+            Recurrence::Monthly(31),
+            Recurrence::Days(3),
+            Recurrence::Days(366),
+        ] {
+            let todo = Todo {
+                name: "Repeat".to_string(),
+                due: Some(start),
+                recurrence: Some(recurrence.clone()),
+                ..Todo::default()
+            };
+            let drafts = parse(&format_calendar("Todos", &[&todo], &[]));
+            assert_eq!(drafts[0].recurrence, Some(recurrence));
+        }
+        // rules which repeat the same way as a simpler one
+        assert_eq!(
+            parse_rrule(&format_rrule(&Recurrence::Days(1)), start),
+            Ok(Recurrence::Daily)
+        );
+        let weekdays = Recurrence::Weekly(WEEKDAYS.to_vec());
+        assert_eq!(
+            parse_rrule(&format_rrule(&weekdays), start),
+            Ok(Recurrence::Weekdays)
+        );
+    }
+
+    #[test]
+    fn rrules() {
+        let start = NaiveDate::from_ymd_opt(2024, 12, 31).unwrap();
+        let parse = |rule| parse_rrule(rule, start);
+        assert_eq!(
+            parse("FREQ=WEEKLY"),
+            Ok(Recurrence::Weekly(vec![Weekday::Tue]))
+        );
+        assert_eq!(parse("freq=weekly;interval=2"), Ok(Recurrence::Days(14)));
+        assert_eq!(parse("FREQ=MONTHLY"), Ok(Recurrence::Monthly(31)));
+        assert_eq!(
+            parse("FREQ=WEEKLY;BYDAY=FR,MO,FR;COUNT=5;WKST=MO"),
+            Ok(Recurrence::Weekly(vec![Weekday::Mon, Weekday::Fri]))
+        );
+        assert!(parse("FREQ=YEARLY").is_err());
+        assert!(parse("FREQ=MONTHLY;INTERVAL=2").is_err());
+        assert!(parse("FREQ=WEEKLY;BYDAY=1MO").is_err());
+        assert!(parse("FREQ=DAILY;BYHOUR=9").is_err());
+    }
+}
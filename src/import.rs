@@ -0,0 +1,1030 @@
+//! Import of todos from the export formats: JSON, CSV, Markdown checklists,
+//! todo.txt and iCalendar. An upload first shows a preview with the duplicates and the
+//! lines which could not be read, the todos are only added after confirming.
+
+use std::sync::Mutex;
+
+use actix_multipart::form::{bytes::Bytes, text::Text, MultipartForm};
+use actix_web::{get, post, web, HttpResponse};
+use chrono::NaiveDate;
+use maud::{html, Markup, DOCTYPE};
+use serde::Deserialize;
+use serde_json::value::RawValue;
+
+use crate::{
+    audit,
+    auth::CurrentUser,
+    events::TodoEvent,
+    export::{Format, CSV_COLUMNS},
+    ical, owns_list, tags,
+    todo::{self, Priority, Recurrence, Tag, Todo, TodoList},
+    todotxt, ApiError, AppState,
+};
+
+/// A todo read from a file, not stored yet.
+#[derive(Debug, Default, Clone)]
+pub struct Draft {
+    /// Line in the file, starting at 1.
+    pub line: usize,
+    /// The id of the todo in the file, which its subtasks refer to.
+    pub key: Option<String>,
+    /// The id of the parent in the file, resolved into `parent`.
+    pub parent_key: Option<String>,
+    /// Index of the parent among the drafts.
+    pub parent: Option<usize>,
+    pub name: String,
+    pub done: bool,
+    pub due: Option<NaiveDate>,
+    pub priority: Priority,
+    pub tags: Vec<String>,
+    pub contexts: Vec<String>,
+    pub recurrence: Option<Recurrence>,
+    pub routine: bool,
+    pub created: Option<NaiveDate>,
+    pub completed: Option<NaiveDate>,
+}
+
+/// A line which could not be read. The other lines are imported anyway.
+pub struct LineError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl LineError {
+    pub fn new(line: usize, message: impl Into<String>) -> Self {
+        LineError {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+/// Guesses the format from the extension of the file, or else from the
+/// content.
+fn detect(file_name: Option<&str>, content: &str) -> Format {
+    let extension = file_name
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(_, extension)| extension.to_lowercase());
+    match extension.as_deref() {
+        Some("json") => return Format::Json,
+        Some("csv") => return Format::Csv,
+        Some("md" | "markdown") => return Format::Md,
+        Some("txt") => return Format::Txt,
+        Some("ics" | "ical") => return Format::Ics,
+        _ => {}
+    }
+    let first = content.lines().find(|line| !line.trim().is_empty());
+    match first.map(str::trim) {
+        Some(line) if line.starts_with('[') => Format::Json,
+        Some(line) if line.eq_ignore_ascii_case("BEGIN:VCALENDAR") => Format::Ics,
+        Some(line) if line.starts_with('#') || line.starts_with("- ") || line.starts_with("* ") => {
+            Format::Md
+        }
+        Some(line) if line.contains(',') && line.to_lowercase().contains("name") => Format::Csv,
+        _ => Format::Txt,
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonTag {
+    Name(String),
+    Tag(Tag),
+}
+
+/// A todo of the JSON export or the JSON API, only the name is required.
+#[derive(Deserialize)]
+struct JsonTodo {
+    id: Option<u128>,
+    parent_id: Option<u128>,
+    name: String,
+    #[serde(default)]
+    done: bool,
+    #[serde(default)]
+    due: Option<NaiveDate>,
+    #[serde(default)]
+    priority: Priority,
+    #[serde(default)]
+    tags: Vec<JsonTag>,
+    #[serde(default)]
+    contexts: Vec<String>,
+    #[serde(default)]
+    recurrence: Option<Recurrence>,
+    #[serde(default)]
+    routine: bool,
+    #[serde(default)]
+    created: Option<NaiveDate>,
+    #[serde(default)]
+    completed: Option<NaiveDate>,
+}
+
+/// The line of `part`, which is a slice of `content`.
+fn line_of(content: &str, part: &str) -> usize {
+    let offset = part.as_ptr() as usize - content.as_ptr() as usize;
+    content[..offset].matches('\n').count() + 1
+}
+
+fn parse_json(content: &str, errors: &mut Vec<LineError>) -> Vec<Draft> {
+    let items: Vec<&RawValue> = match serde_json::from_str(content) {
+        Ok(items) => items,
+        Err(err) => {
+            errors.push(LineError::new(
+                err.line(),
+                "The file is no JSON array of todos",
+            ));
+            return vec![];
+        }
+    };
+    let mut drafts = vec![];
+    for item in items {
+        let line = line_of(content, item.get());
+        let todo: JsonTodo = match serde_json::from_str(item.get()) {
+            Ok(todo) => todo,
+            Err(err) => {
+                // the position is relative to the todo, the line is known
+                let message = err.to_string();
+                let message = match message.rsplit_once(" at line ") {
+                    Some((message, _)) => message,
+                    None => &message,
+                };
+                errors.push(LineError::new(line, format!("Invalid todo: {}", message)));
+                continue;
+            }
+        };
+        let tags = todo.tags.iter().map(|tag| match tag {
+            JsonTag::Name(name) => name.as_str(),
+            JsonTag::Tag(tag) => tag.name.as_str(),
+        });
+        let checked = todo::validate_name(&todo.name).and_then(|name| {
+            let contexts = todo::validate_tags(todo.contexts.iter().map(String::as_str))?;
+            Ok((name, todo::validate_tags(tags)?, contexts))
+        });
+        match checked {
+            Ok((name, tags, contexts)) => drafts.push(Draft {
+                line,
+                key: todo.id.map(|id| id.to_string()),
+                parent_key: todo.parent_id.map(|id| id.to_string()),
+                name,
+                done: todo.done,
+                due: todo.due,
+                priority: todo.priority,
+                tags,
+                contexts,
+                recurrence: todo.recurrence,
+                routine: todo.routine,
+                created: todo.created,
+                completed: todo.completed,
+                ..Draft::default()
+            }),
+            Err(error) => errors.push(LineError::new(line, error)),
+        }
+    }
+    drafts
+}
+
+/// Splits CSV into records of fields with the line each record starts on.
+/// Quoted fields may contain separators, `""` and line breaks.
+fn csv_records(content: &str) -> Vec<(usize, Result<Vec<String>, &'static str>)> {
+    let mut records = vec![];
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut start = 1;
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.next_if_eq(&'"').is_some() => field.push('"'),
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            ('\r', false) if chars.peek() == Some(&'\n') => {}
+            ('\n', false) => {
+                fields.push(std::mem::take(&mut field));
+                records.push((start, Ok(std::mem::take(&mut fields))));
+                line += 1;
+                start = line;
+            }
+            ('\n', true) => {
+                field.push(c);
+                line += 1;
+            }
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        records.push((start, Err("A quoted field is not closed")));
+    } else if !field.is_empty() || !fields.is_empty() {
+        fields.push(field);
+        records.push((start, Ok(fields)));
+    }
+    records
+}
+
+fn parse_bool(value: &str) -> Result<bool, &'static str> {
+    match value.trim().to_lowercase().as_str() {
+        "" | "false" | "no" | "0" => Ok(false),
+        "true" | "yes" | "x" | "1" => Ok(true),
+        _ => Err("Use true or false for done and routine"),
+    }
+}
+
+/// Reads the columns of the CSV export by the names in the first line. Only
+/// `name` is required, unknown columns are ignored.
+fn parse_csv(content: &str, errors: &mut Vec<LineError>) -> Vec<Draft> {
+    let mut records = csv_records(content).into_iter();
+    let header: Vec<String> = match records.next() {
+        Some((_, Ok(header))) => header
+            .iter()
+            .map(|name| name.trim().to_lowercase())
+            .collect(),
+        _ => vec![],
+    };
+    if !header.iter().any(|name| name == "name") {
+        let message = format!(
+            "The first line must name the columns, e.g. {}",
+            CSV_COLUMNS.join(",")
+        );
+        errors.push(LineError::new(1, message));
+        return vec![];
+    }
+    let mut drafts = vec![];
+    for (line, record) in records {
+        let fields = match record {
+            Ok(fields) if fields.iter().all(|field| field.trim().is_empty()) => continue,
+            Ok(fields) => fields,
+            Err(error) => {
+                errors.push(LineError::new(line, error));
+                continue;
+            }
+        };
+        let column = |name: &str| {
+            header
+                .iter()
+                .position(|column| column == name)
+                .and_then(|index| fields.get(index))
+                .map(|field| field.trim())
+                .unwrap_or_default()
+        };
+        let key = |name: &str| Some(column(name).to_string()).filter(|key| !key.is_empty());
+        let draft = (|| {
+            Ok::<_, &'static str>(Draft {
+                line,
+                key: key("id"),
+                parent_key: key("parent_id"),
+                name: todo::validate_name(column("name"))?,
+                done: parse_bool(column("done"))?,
+                due: todo::parse_due(column("due"))?,
+                priority: match column("priority") {
+                    "" => Priority::Normal,
+                    priority => Priority::parse(&priority.to_lowercase())
+                        .ok_or("The priority must be low, normal, high or urgent")?,
+                },
+                tags: todo::parse_tags(column("tags"))?,
+                contexts: todo::parse_tags(column("contexts"))?,
+                recurrence: Recurrence::parse(column("recurrence"))?,
+                routine: parse_bool(column("routine"))?,
+                created: todo::parse_due(column("created"))
+                    .map_err(|_| "The creation date is invalid")?,
+                completed: todo::parse_due(column("completed"))
+                    .map_err(|_| "The completion date is invalid")?,
+                ..Draft::default()
+            })
+        })();
+        match draft {
+            Ok(draft) => drafts.push(draft),
+            Err(error) => errors.push(LineError::new(line, error)),
+        }
+    }
+    drafts
+}
+
+/// Reads the details of the Markdown export like `(due 2024-12-31, high
+/// priority, repeats daily)`. Returns `None` if a part is no detail, then the
+/// parentheses belong to the name.
+fn parse_details(details: &str, draft: &mut Draft) -> Option<()> {
+    let mut parsed = draft.clone();
+    for part in details.split(", ") {
+        if let Some(due) = part.strip_prefix("due ") {
+            parsed.due = todo::parse_due(due).ok()?;
+        } else if let Some(priority) = part.strip_suffix(" priority") {
+            parsed.priority = Priority::parse(priority)?;
+        } else if let Some(rule) = part.strip_prefix("repeats ") {
+            parsed.recurrence = Recurrence::parse(rule).ok()?;
+        } else {
+            return None;
+        }
+    }
+    *draft = parsed;
+    Some(())
+}
+
+/// Reads list items like `- [x] Buy milk #shopping`, items without checkbox
+/// are open todos. Indented items are subtasks of the todo above them.
+/// Headings and empty lines are skipped.
+fn parse_markdown(content: &str, errors: &mut Vec<LineError>) -> Vec<Draft> {
+    let mut drafts: Vec<Draft> = vec![];
+    // the index of the last todo, `Err` if it could not be read
+    let mut parent: Result<Option<usize>, ()> = Ok(None);
+    for (index, text) in content.lines().enumerate() {
+        let line = index + 1;
+        if text.trim().is_empty() || text.starts_with('#') {
+            continue;
+        }
+        let indented = text.starts_with([' ', '\t']);
+        let Some(item) = text
+            .trim_start()
+            .strip_prefix(['-', '*', '+'])
+            .and_then(|item| item.strip_prefix(' '))
+        else {
+            errors.push(LineError::new(
+                line,
+                "Expected a list item like - [ ] Buy milk",
+            ));
+            continue;
+        };
+        let (done, item) = match item.get(..4) {
+            Some("[ ] ") => (false, &item[4..]),
+            Some("[x] " | "[X] ") => (true, &item[4..]),
+            _ => (false, item),
+        };
+        let mut draft = Draft {
+            line,
+            done,
+            ..Draft::default()
+        };
+        let mut item = item.trim();
+        if let Some((name, details)) = item
+            .strip_suffix(')')
+            .and_then(|item| item.rsplit_once(" ("))
+        {
+            if parse_details(details, &mut draft).is_some() {
+                item = name;
+            }
+        }
+        let (name, tags) = todo::parse_prompt(item);
+        let name = match todo::validate_name(&name) {
+            Ok(name) => name,
+            Err(error) => {
+                errors.push(LineError::new(line, error));
+                if !indented {
+                    parent = Err(());
+                }
+                continue;
+            }
+        };
+        draft.name = name;
+        draft.tags = tags;
+        if indented {
+            match parent {
+                Ok(Some(parent)) => draft.parent = Some(parent),
+                Ok(None) => {
+                    errors.push(LineError::new(line, "A subtask needs a todo above it"));
+                    continue;
+                }
+                Err(()) => {
+                    errors.push(LineError::new(
+                        line,
+                        "The todo of the subtask could not be read",
+                    ));
+                    continue;
+                }
+            }
+        } else {
+            parent = Ok(Some(drafts.len()));
+        }
+        drafts.push(draft);
+    }
+    drafts
+}
+
+fn parse_todotxt(content: &str, errors: &mut Vec<LineError>) -> Vec<Draft> {
+    let mut drafts = vec![];
+    for (index, text) in content.lines().enumerate() {
+        if text.trim().is_empty() {
+            continue;
+        }
+        match todotxt::parse_line(text) {
+            Ok(draft) => drafts.push(Draft {
+                line: index + 1,
+                ..draft
+            }),
+            Err(error) => errors.push(LineError::new(index + 1, error)),
+        }
+    }
+    drafts
+}
+
+/// Links the subtasks to their parents by the ids in the file. Subtasks whose
+/// parent is missing or a subtask itself are dropped with an error.
+fn resolve_parents(drafts: Vec<Draft>, errors: &mut Vec<LineError>) -> Vec<Draft> {
+    let parents: Vec<Option<Option<usize>>> = drafts
+        .iter()
+        .map(|draft| {
+            let key = draft.parent_key.as_ref()?;
+            Some(drafts.iter().position(|parent| {
+                parent.key.as_ref() == Some(key)
+                    && parent.parent_key.is_none()
+                    && parent.parent.is_none()
+            }))
+        })
+        .collect();
+    // indices shift when subtasks are dropped
+    let mut indices = vec![];
+    let mut next = 0;
+    for parent in &parents {
+        indices.push(next);
+        if parent != &Some(None) {
+            next += 1;
+        }
+    }
+    let mut resolved = vec![];
+    for (mut draft, parent) in drafts.into_iter().zip(parents) {
+        match parent {
+            Some(None) => {
+                errors.push(LineError::new(
+                    draft.line,
+                    "The parent todo is not in the file or is a subtask itself",
+                ));
+                continue;
+            }
+            Some(Some(parent)) => draft.parent = Some(parent),
+            None => {}
+        }
+        draft.parent = draft.parent.map(|parent| indices[parent]);
+        resolved.push(draft);
+    }
+    resolved
+}
+
+/// Reads the todos of the file. Errors are reported per line, the readable
+/// lines are returned in the order of the file.
+pub fn parse(format: Format, content: &str) -> (Vec<Draft>, Vec<LineError>) {
+    let mut errors = vec![];
+    let drafts = match format {
+        Format::Json => parse_json(content, &mut errors),
+        Format::Csv => parse_csv(content, &mut errors),
+        Format::Md => parse_markdown(content, &mut errors),
+        Format::Txt => parse_todotxt(content, &mut errors),
+        Format::Ics => ical::parse_calendar(content, &mut errors),
+    };
+    let drafts = resolve_parents(drafts, &mut errors);
+    errors.sort_by_key(|error| error.line);
+    (drafts, errors)
+}
+
+/// A draft which is already in the list, or appears earlier in the file.
+#[derive(Clone, Copy)]
+enum Duplicate {
+    Existing(u128),
+    InFile(usize),
+}
+
+/// Finds the drafts with the same name as an existing todo or an earlier
+/// draft, ignoring the case. Subtasks are compared with the subtasks of the
+/// same todo.
+fn find_duplicates(existing: &[Todo], drafts: &[Draft]) -> Vec<Option<Duplicate>> {
+    #[derive(PartialEq)]
+    enum Parent {
+        Root,
+        Todo(u128),
+        Draft(usize),
+    }
+    let same = |a: &str, b: &str| a.to_lowercase() == b.to_lowercase();
+    let mut duplicates: Vec<Option<Duplicate>> = vec![None; drafts.len()];
+    let mut parents: Vec<Parent> = drafts.iter().map(|_| Parent::Root).collect();
+    let top = (0..drafts.len()).filter(|&i| drafts[i].parent.is_none());
+    let subtasks = (0..drafts.len()).filter(|&i| drafts[i].parent.is_some());
+    for i in top.chain(subtasks) {
+        let draft = &drafts[i];
+        parents[i] = match draft.parent {
+            None => Parent::Root,
+            Some(parent) => match duplicates[parent] {
+                Some(Duplicate::Existing(id)) => Parent::Todo(id),
+                Some(Duplicate::InFile(first)) => Parent::Draft(first),
+                None => Parent::Draft(parent),
+            },
+        };
+        let existing_parent = match parents[i] {
+            Parent::Root => Some(None),
+            Parent::Todo(id) => Some(Some(id)),
+            Parent::Draft(_) => None,
+        };
+        duplicates[i] = existing
+            .iter()
+            .find(|todo| existing_parent == Some(todo.parent_id) && same(&todo.name, &draft.name))
+            .map(|todo| Duplicate::Existing(todo.id))
+            .or_else(|| {
+                (0..i)
+                    .find(|&j| {
+                        drafts[j].parent.is_some() == draft.parent.is_some()
+                            && parents[j] == parents[i]
+                            && same(&drafts[j].name, &draft.name)
+                    })
+                    .map(Duplicate::InFile)
+            });
+    }
+    duplicates
+}
+
+/// Adds the drafts to the list with fresh ids, todos before their subtasks.
+/// Skipped duplicates keep their subtasks, which are added to the existing
+/// todo. Returns the number of added todos.
+fn commit(
+    state: &mut AppState,
+    user: CurrentUser,
+    list_id: u128,
+    drafts: &[Draft],
+    skip_duplicates: bool,
+) -> Result<usize, ApiError> {
+    let existing = state.store.list(Some(list_id))?;
+    let duplicates = find_duplicates(&existing, drafts);
+    let mut ids: Vec<Option<u128>> = vec![None; drafts.len()];
+    let mut added = 0;
+    let top = (0..drafts.len()).filter(|&i| drafts[i].parent.is_none());
+    let subtasks = (0..drafts.len()).filter(|&i| drafts[i].parent.is_some());
+    for i in top.chain(subtasks) {
+        let draft = &drafts[i];
+        if skip_duplicates {
+            match duplicates[i] {
+                Some(Duplicate::Existing(id)) => {
+                    ids[i] = Some(id);
+                    continue;
+                }
+                Some(Duplicate::InFile(first)) => {
+                    ids[i] = ids[first];
+                    continue;
+                }
+                None => {}
+            }
+        }
+        let tags = tags::resolve(&*state.store, user.0, draft.tags.clone())?;
+        let todo = state.store.create(Todo {
+            list_id,
+            parent_id: draft.parent.and_then(|parent| ids[parent]),
+            name: draft.name.clone(),
+            done: draft.done,
+            created: draft.created,
+            completed: draft.completed.or(draft.done.then(todo::today)),
+            due: draft.due,
+            priority: draft.priority,
+            tags,
+            contexts: draft.contexts.clone(),
+            recurrence: draft.recurrence.clone(),
+            routine: draft.routine,
+            ..Todo::default()
+        })?;
+        audit::created(state, Some(user.0), &todo)?;
+        ids[i] = Some(todo.id);
+        added += 1;
+    }
+    if added > 0 {
+        state.events.send(TodoEvent::ListChanged(list_id));
+    }
+    Ok(added)
+}
+
+/// Describes the duplicate for the preview.
+fn describe(duplicate: Option<Duplicate>, drafts: &[Draft]) -> Option<String> {
+    match duplicate? {
+        Duplicate::Existing(_) => Some("already in the list".to_string()),
+        Duplicate::InFile(first) => Some(format!("same as line {}", drafts[first].line)),
+    }
+}
+
+fn render_draft(draft: &Draft, duplicate: Option<String>) -> Markup {
+    html! {
+        div class="flex flex-row gap-4 items-center" {
+            span class="w-16 text-sm text-neutral-500" { "Line " (draft.line) }
+            div ."flex-1" .line-through[draft.done] { (draft.name) }
+            @for tag in &draft.tags {
+                span class="rounded-full px-2 text-sm bg-neutral-700" { "#" (tag) }
+            }
+            @for context in &draft.contexts {
+                span class="text-sm text-neutral-400" { "@" (context) }
+            }
+            @if draft.priority != Priority::Normal {
+                span class={ "rounded px-2 text-sm " (draft.priority.color()) } { (draft.priority.label()) }
+            }
+            @if let Some(due) = draft.due {
+                span class="text-sm text-neutral-400" { (due.format("%Y-%m-%d")) }
+            }
+            @if let Some(recurrence) = &draft.recurrence {
+                span class="text-sm text-neutral-400" { "↻ " (recurrence) }
+            }
+            @if let Some(duplicate) = duplicate {
+                span class="text-sm text-yellow-400" { (duplicate) }
+            }
+        }
+    }
+}
+
+/// The todos which would be added, with their subtasks, the duplicates and the
+/// errors. The form posts the content again to add the todos.
+fn render_preview(
+    list: &TodoList,
+    format: Format,
+    content: &str,
+    drafts: &[Draft],
+    duplicates: &[Option<Duplicate>],
+    errors: &[LineError],
+) -> Markup {
+    let count = duplicates
+        .iter()
+        .filter(|duplicate| duplicate.is_some())
+        .count();
+    html! {
+        div #import class="flex flex-col gap-4" {
+            p {
+                (drafts.len()) @if drafts.len() == 1 { " todo" } @else { " todos" } " read as " (format.label()) " for " (list.title)
+                @if count > 0 { ", " (count) " of them are duplicates" }
+                @if !errors.is_empty() { ", " (errors.len()) " lines could not be read" }
+                "."
+            }
+            @if !errors.is_empty() {
+                ul class="flex flex-col gap-1 text-sm text-red-500" {
+                    @for error in errors {
+                        li { "Line " (error.line) ": " (error.message) }
+                    }
+                }
+            }
+            ul class="flex flex-col gap-2" {
+                @for (i, draft) in drafts.iter().enumerate().filter(|(_, draft)| draft.parent.is_none()) {
+                    li class="flex flex-col gap-1" {
+                        (render_draft(draft, describe(duplicates[i], drafts)))
+                        @for (j, subtask) in drafts.iter().enumerate().filter(|(_, subtask)| subtask.parent == Some(i)) {
+                            div class="ml-8" { (render_draft(subtask, describe(duplicates[j], drafts))) }
+                        }
+                    }
+                }
+            }
+            @if !drafts.is_empty() {
+                form class="flex flex-row gap-4 items-center" hx-post="/import" hx-encoding="multipart/form-data" hx-target="#import" hx-swap="outerHTML" {
+                    input type="hidden" name="content" value=(content) ;
+                    input type="hidden" name="format" value=(format.as_str()) ;
+                    input type="hidden" name="list_id" value=(list.id) ;
+                    input type="hidden" name="commit" value="true" ;
+                    label class="flex flex-row gap-1 items-center text-sm" {
+                        input type="checkbox" name="skip_duplicates" value="true" checked ;
+                        "Skip duplicates"
+                    }
+                    button class="rounded bg-blue-500 px-4 py-2" { "Import" }
+                }
+            }
+        }
+    }
+}
+
+fn render_form(lists: &[TodoList], error: Option<&str>) -> Markup {
+    html! {
+        div #import class="flex flex-col gap-4" {
+            form class="flex flex-col gap-4" hx-post="/import" hx-encoding="multipart/form-data" hx-target="#import" hx-swap="outerHTML" {
+                input type="file" name="file" accept=".json,.csv,.md,.markdown,.txt,.ics" class="text-sm" ;
+                textarea name="content" rows="6" placeholder="Or paste the todos here" class="border rounded border-neutral-400 text-sm px-2 py-1 bg-black" {}
+                div class="flex flex-row gap-4 items-center" {
+                    select name="format" class="border rounded border-neutral-400 text-sm px-2 py-1 bg-black" {
+                        option value="auto" { "Detect format" }
+                        @for format in Format::ALL {
+                            option value=(format.as_str()) { (format.label()) }
+                        }
+                    }
+                    select name="list_id" title="Add to list" class="border rounded border-neutral-400 text-sm px-2 py-1 bg-black" {
+                        @for list in lists {
+                            option value=(list.id) { (list.title) }
+                        }
+                    }
+                    button class="rounded bg-blue-500 px-4 py-2" { "Preview" }
+                }
+            }
+            @if let Some(error) = error {
+                div class="text-sm text-red-500" { (error) }
+            }
+        }
+    }
+}
+
+#[get("/import")]
+async fn page(
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+) -> Result<HttpResponse, ApiError> {
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let lists = state.store.lists(Some(user.0))?;
+    let body = html! {
+        (DOCTYPE)
+        script src="/assets/tailwind.min.js" {}
+        script src="/assets/htmx.min.js"{}
+        link rel="icon" type="image/png" href="/assets/favicon.png";
+        link src="/assets/global.css" rel="stylesheet" {}
+        title { "Import - Todo" }
+
+        body ."min-h-sreen" .text-white .bg-black ."p-4" {
+            main class="container m-auto max-w-2xl flex flex-col gap-4" {
+                a href="/" class="text-sm text-neutral-400" { "← Lists" }
+                h1 class="text-2xl" { "Import" }
+                p class="text-sm text-neutral-400" {
+                    "Adds todos from JSON, CSV, a Markdown checklist, todo.txt or the tasks of an iCalendar file, e.g. a file of the export. The todos are shown before they are added."
+                }
+                (render_form(&lists, None))
+            }
+        }
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body.into_string()))
+}
+
+#[derive(MultipartForm)]
+struct ImportForm {
+    file: Option<Bytes>,
+    content: Option<Text<String>>,
+    /// A format or `auto`.
+    format: Text<String>,
+    list_id: Text<String>,
+    /// Adds the todos instead of showing the preview.
+    commit: Option<Text<String>>,
+    skip_duplicates: Option<Text<String>>,
+}
+
+/// Reads the uploaded file or the pasted text and picks its format. The error
+/// is the message for the form.
+fn read_upload(form: ImportForm) -> Result<(Format, String), &'static str> {
+    let (file_name, content) = match form.file.filter(|file| !file.data.is_empty()) {
+        Some(file) => match String::from_utf8(file.data.to_vec()) {
+            Ok(content) => (file.file_name, content),
+            Err(_) => return Err("The file is no UTF-8 text"),
+        },
+        None => (None, form.content.map(Text::into_inner).unwrap_or_default()),
+    };
+    let content = content.trim_start_matches('\u{feff}');
+    if content.trim().is_empty() {
+        return Err("Choose a file or paste the todos");
+    }
+    let format = match form.format.as_str() {
+        "auto" => detect(file_name.as_deref(), content),
+        format => match Format::parse(format) {
+            Some(format) => format,
+            None => return Err("The format is not supported"),
+        },
+    };
+    Ok((format, content.to_string()))
+}
+
+/// Shows the preview of the uploaded file or the pasted text, or adds the
+/// todos when the preview is confirmed.
+#[post("/import")]
+async fn upload(
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+    form: MultipartForm<ImportForm>,
+) -> Result<HttpResponse, ApiError> {
+    let form = form.into_inner();
+    let list_id = form.list_id.parse::<u128>().ok();
+    let add = form.commit.is_some();
+    let skip_duplicates = form.skip_duplicates.is_some();
+    // the upload is parsed before taking the lock, so a large file does not
+    // hold up the other requests
+    let upload = read_upload(form).map(|(format, content)| {
+        let (drafts, errors) = parse(format, &content);
+        (format, content, drafts, errors)
+    });
+    let mut state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let list = match list_id {
+        Some(list_id) if owns_list(&*state.store, user, list_id)? => {
+            state.store.get_list(list_id)?
+        }
+        _ => None,
+    };
+    let Some(list) = list else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let (format, content, drafts, errors) = match upload {
+        Ok(upload) => upload,
+        Err(message) => {
+            let lists = state.store.lists(Some(user.0))?;
+            return Ok(HttpResponse::Ok().body(render_form(&lists, Some(message)).into_string()));
+        }
+    };
+    if add {
+        let added = commit(&mut state, user, list.id, &drafts, skip_duplicates)?;
+        drop(state);
+        let body = html! {
+            div #import class="flex flex-col gap-4" {
+                p {
+                    "Added " (added) @if added == 1 { " todo" } @else { " todos" } " to "
+                    a href=(list.url()) class="text-blue-400" { (list.title) } "."
+                }
+                a href="/import" class="text-sm text-neutral-400" { "Import another file" }
+            }
+        };
+        return Ok(HttpResponse::Ok().body(body.into_string()));
+    }
+    let existing = state.store.list(Some(list.id))?;
+    drop(state);
+    let duplicates = find_duplicates(&existing, &drafts);
+    let preview = render_preview(&list, format, &content, &drafts, &duplicates, &errors);
+    Ok(HttpResponse::Ok().body(preview.into_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(errors: &[LineError]) -> Vec<usize> {
+        errors.iter().map(|error| error.line).collect()
+    }
+
+    #[test]
+    fn csv_quoted_fields() {
+        let records = csv_records("a,\"b, c\",\"say \"\"hi\"\"\"\r\n\"two\nlines\",x\nlast,y");
+        let records: Vec<(usize, Vec<String>)> = records
+            .into_iter()
+            .map(|(line, record)| (line, record.unwrap()))
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                (1, vec!["a".into(), "b, c".into(), "say \"hi\"".into()]),
+                (2, vec!["two\nlines".into(), "x".into()]),
+                (4, vec!["last".into(), "y".into()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_unclosed_quote() {
+        let records = csv_records("a,b\n\"open,c\nd");
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].0, 2);
+        assert!(records[1].1.is_err());
+    }
+
+    #[test]
+    fn csv_error_lines() {
+        let content =
+            "name,done,priority\nMilk,true,high\n\"Bread\nrye\",maybe,\nEggs,,highest\n,,\n";
+        let (drafts, errors) = parse(Format::Csv, content);
+        assert_eq!(drafts.len(), 1);
+        assert_eq!(drafts[0].name, "Milk");
+        assert!(drafts[0].done);
+        assert_eq!(drafts[0].priority, Priority::High);
+        // the empty row at the end is skipped
+        assert_eq!(lines(&errors), vec![3, 5]);
+    }
+
+    #[test]
+    fn csv_needs_header() {
+        let (drafts, errors) = parse(Format::Csv, "Milk,true\n");
+        assert!(drafts.is_empty());
+        assert_eq!(lines(&errors), vec![1]);
+    }
+
+    #[test]
+    fn json_error_lines() {
+        let content =
+            "[\n  {\"name\": \"Milk\"},\n  {\"name\": \"\"},\n  {\n    \"done\": true\n  }\n]";
+        let (drafts, errors) = parse(Format::Json, content);
+        assert_eq!(drafts.len(), 1);
+        assert_eq!(lines(&errors), vec![3, 4]);
+        assert!(errors[1]
+            .message
+            .starts_with("Invalid todo: missing field `name`"));
+        let (_, errors) = parse(Format::Json, "[\n{\"name\": \"Milk\"\n");
+        assert_eq!(lines(&errors), vec![3]);
+    }
+
+    #[test]
+    fn json_subtasks() {
+        let content = r#"[{"id": 7, "name": "Shop"}, {"id": 8, "parent_id": 7, "name": "Milk"}, {"parent_id": 9, "name": "Eggs"}]"#;
+        let (drafts, errors) = parse(Format::Json, content);
+        assert_eq!(drafts.len(), 2);
+        assert_eq!(drafts[1].parent, Some(0));
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn markdown_subtasks() {
+        let content = "# Shopping\n\n- [ ] Shop #errand\n  - [x] Milk (due 2024-12-31, high priority)\n  - Eggs (2 pieces)\n* [X] Call mom\n\t- [ ] Birthday\n";
+        let (drafts, errors) = parse(Format::Md, content);
+        assert!(errors.is_empty());
+        let parents: Vec<Option<usize>> = drafts.iter().map(|draft| draft.parent).collect();
+        assert_eq!(parents, vec![None, Some(0), Some(0), None, Some(3)]);
+        assert_eq!(drafts[0].tags, vec!["errand".to_string()]);
+        assert!(drafts[1].done);
+        assert_eq!(drafts[1].name, "Milk");
+        assert_eq!(drafts[1].due, NaiveDate::from_ymd_opt(2024, 12, 31));
+        assert_eq!(drafts[1].priority, Priority::High);
+        assert_eq!(drafts[2].name, "Eggs (2 pieces)");
+        assert!(drafts[3].done);
+        assert_eq!(drafts[4].line, 7);
+    }
+
+    #[test]
+    fn markdown_error_lines() {
+        let content = "  - [ ] Orphan\nno item\n- [ ] \n  - [ ] Lost\n- [ ] Fine\n";
+        let (drafts, errors) = parse(Format::Md, content);
+        assert_eq!(drafts.len(), 1);
+        assert_eq!(lines(&errors), vec![1, 2, 3, 4]);
+        assert_eq!(
+            errors[3].message,
+            "The todo of the subtask could not be read"
+        );
+    }
+
+    #[test]
+    fn detect_format() {
+        assert!(detect(Some("todos.JSON"), "") == Format::Json);
+        assert!(detect(Some("todos.csv"), "[") == Format::Csv);
+        assert!(detect(Some("todo.txt"), "- [ ] Milk") == Format::Txt);
+        assert!(detect(Some("tasks.ics"), "") == Format::Ics);
+        assert!(detect(None, "\n  [{\"name\": \"Milk\"}]") == Format::Json);
+        assert!(detect(None, "begin:vcalendar\nEND:VCALENDAR") == Format::Ics);
+        assert!(detect(None, "# Todos\n- [ ] Milk") == Format::Md);
+        assert!(detect(None, "* Milk") == Format::Md);
+        assert!(detect(None, "id,Name,done\n1,Milk,false") == Format::Csv);
+        assert!(detect(None, "(A) Call mom, then dad") == Format::Txt);
+        assert!(detect(Some("notes"), "x Milk") == Format::Txt);
+    }
+
+    #[test]
+    fn resolve_parent_keys() {
+        let draft = |key: &str, parent: Option<&str>| Draft {
+            key: Some(key.to_string()),
+            parent_key: parent.map(str::to_string),
+            name: key.to_string(),
+            ..Draft::default()
+        };
+        let drafts = vec![
+            draft("b", Some("a")),
+            draft("x", Some("missing")),
+            draft("a", None),
+            draft("c", Some("b")),
+            draft("d", Some("a")),
+        ];
+        let mut errors = vec![];
+        let resolved = resolve_parents(drafts, &mut errors);
+        let names: Vec<(&str, Option<usize>)> = resolved
+            .iter()
+            .map(|draft| (draft.name.as_str(), draft.parent))
+            .collect();
+        assert_eq!(names, vec![("b", Some(1)), ("a", None), ("d", Some(1))]);
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn duplicates() {
+        let existing = vec![
+            Todo {
+                id: 1,
+                name: "Shop".to_string(),
+                ..Todo::default()
+            },
+            Todo {
+                id: 2,
+                parent_id: Some(1),
+                name: "Milk".to_string(),
+                ..Todo::default()
+            },
+        ];
+        let draft = |name: &str, parent: Option<usize>| Draft {
+            name: name.to_string(),
+            parent,
+            ..Draft::default()
+        };
+        let drafts = vec![
+            draft("shop", None),
+            draft("MILK", Some(0)),
+            draft("Eggs", Some(0)),
+            draft("Milk", None),
+            draft("milk", None),
+            draft("New", None),
+            draft("eggs", Some(5)),
+            draft("Eggs", Some(5)),
+        ];
+        let found: Vec<Option<String>> = find_duplicates(&existing, &drafts)
+            .into_iter()
+            .map(|duplicate| match duplicate {
+                Some(Duplicate::Existing(id)) => Some(format!("todo {}", id)),
+                Some(Duplicate::InFile(index)) => Some(format!("draft {}", index)),
+                None => None,
+            })
+            .collect();
+        let expected = [
+            Some("todo 1"),
+            Some("todo 2"),
+            None,
+            None,
+            Some("draft 3"),
+            None,
+            None,
+            Some("draft 6"),
+        ];
+        assert_eq!(found, expected.map(|item| item.map(str::to_string)));
+    }
+}
@@ -0,0 +1,320 @@
+//! Handlers for the todo lists: the page of a list with the sidebar and the
+//! actions to create, rename and delete lists.
+
+use std::sync::Mutex;
+
+use actix_web::{
+    body::{BoxBody, EitherBody, MessageBody},
+    delete,
+    dev::{ServiceRequest, ServiceResponse},
+    get,
+    middleware::Next,
+    post, web, HttpRequest, HttpResponse, Responder,
+};
+use maud::{html, Markup, DOCTYPE};
+use serde::Deserialize;
+
+use crate::{
+    auth::{CurrentUser, User},
+    events::{self, TodoEvent},
+    export::Format,
+    owns_list, path_id, render_filters, render_list, render_statistic, store,
+    todo::{self, Priority, Recurrence, TodoList},
+    undo, ApiError, AppState, ViewQuery,
+};
+
+/// Answers requests for lists of other users with `404`, as if the list did
+/// not exist. Pages of a deleted list go back to the start page with their
+/// next htmx request.
+pub async fn require_owner(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody, BoxBody>>, actix_web::Error> {
+    let owner = match (
+        req.extract::<CurrentUser>().await,
+        req.app_data::<web::Data<Mutex<AppState>>>(),
+    ) {
+        (Ok(user), Some(data)) => {
+            let state = data.lock().map_err(|_| ApiError { name: "mutex lock" })?;
+            owns_list(&*state.store, user, path_id(req.request(), "list_id")?)?
+        }
+        _ => false,
+    };
+    if owner {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+    let mut response = HttpResponse::NotFound();
+    if req.headers().contains_key("HX-Request") {
+        response.append_header(("HX-Redirect", "/"));
+    }
+    Ok(req.into_response(response.finish()).map_into_right_body())
+}
+
+fn render_user(user: &User) -> Markup {
+    html! {
+        div class="flex flex-row gap-2 items-center text-sm text-neutral-400" {
+            span class="flex-1" { (user.name) }
+            button hx-post="/logout" {"Logout"}
+        }
+    }
+}
+
+fn render_sidebar(lists: &[TodoList], current: u128, oob: bool) -> Markup {
+    html! {
+        nav #lists class="flex flex-col gap-2" hx-swap-oob=[oob.then_some("true")] {
+            @for list in lists {
+                a href=(list.url()) .rounded ."px-2" ."py-1" ."hover:bg-neutral-800" .bg-neutral-800[list.id == current] {
+                    (list.title)
+                }
+            }
+        }
+    }
+}
+
+fn render_header(list: &TodoList) -> Markup {
+    html! {
+        div #list-header class="flex flex-row gap-4 items-center" {
+            h1 class="text-2xl flex-1" {
+                (list.title)
+            }
+            details class="relative text-sm text-neutral-400" {
+                summary class="cursor-pointer" { "Export" }
+                div class="absolute right-0 z-10 mt-1 flex flex-col gap-1 rounded border border-neutral-600 bg-black px-4 py-2 whitespace-nowrap" {
+                    @for format in Format::ALL {
+                        a href=(format!("/export?format={}&list_id={}", format.as_str(), list.id)) { (format.label()) }
+                    }
+                    @for format in Format::ALL {
+                        a href=(format!("/export?format={}", format.as_str())) { "All lists as " (format.label()) }
+                    }
+                    a href=(format!("/calendar.ics?list_id={}", list.id)) title="Copy the link to subscribe in a calendar app" { "Calendar feed" }
+                    a href="/calendar.ics" title="Copy the link to subscribe in a calendar app" { "Calendar feed of all lists" }
+                }
+            }
+            button class="text-sm text-neutral-400" hx-get=(format!("{}/rename", list.url())) hx-target="#list-header" hx-swap="outerHTML" {"Rename"}
+            button class="text-sm text-red-500" hx-delete=(list.url()) hx-confirm=(format!("Delete the list '{}' with all its todos?", list.title)) {"Delete list"}
+        }
+    }
+}
+
+fn render_rename_form(list: &TodoList, value: &str, error: Option<&str>) -> Markup {
+    html! {
+        div #list-header class="flex flex-col gap-1" {
+            form class="flex flex-row gap-4" hx-post=(format!("{}/rename", list.url())) hx-target="#list-header" hx-swap="outerHTML" {
+                input name="title" value=(value) autofocus class="flex-1 border rounded border-neutral-400 text-sm px-4 py-2 bg-black" ;
+                button class="rounded bg-blue-500 px-4 py-2" {"Save"}
+                button type="button" class="rounded border border-neutral-400 px-4 py-2" hx-get=(format!("{}/header", list.url())) hx-target="#list-header" hx-swap="outerHTML" {"Cancel"}
+            }
+            @if let Some(error) = error {
+                div class="text-sm text-red-500" { (error) }
+            }
+        }
+    }
+}
+
+/// Redirects to the first list.
+#[get("/")]
+async fn index(
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+) -> Result<HttpResponse, ApiError> {
+    let mut state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let list = store::first_list(&mut *state.store, user.0)?;
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", list.url()))
+        .finish())
+}
+
+#[get("")]
+async fn show(
+    req: HttpRequest,
+    user: CurrentUser,
+    query: web::Query<ViewQuery>,
+    data: web::Data<Mutex<AppState>>,
+) -> Result<HttpResponse, ApiError> {
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let Some(list) = state.store.get_list(path_id(&req, "list_id")?)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let Some(account) = state.store.get_user(user.0)? else {
+        return Err(ApiError {
+            name: "unknown user",
+        });
+    };
+    let lists = state.store.lists(Some(user.0))?;
+    let todos = state.store.list(Some(list.id))?;
+    let body = html! {
+        (DOCTYPE)
+        script src="/assets/tailwind.min.js" {}
+        script src="/assets/htmx.min.js"{}
+        script src="/assets/ext/sse.js"{}
+        script src="/assets/reorder.js"{}
+        link rel="icon" type="image/png" href="/assets/favicon.png";
+        link src="/assets/global.css" rel="stylesheet" {}
+        title {
+            (list.title) " - Todo"
+        }
+
+        body ."min-h-sreen" .text-white .bg-black ."p-4" {
+        div class="container m-auto flex flex-row gap-8" {
+            aside class="w-64 flex flex-col gap-4" {
+                (render_user(&account))
+                h2 class="text-lg text-neutral-400" { "Lists" }
+                (render_sidebar(&lists, list.id, false))
+                form class="flex flex-col gap-1" hx-post="/lists" hx-target="#new-list-error" {
+                    input name="title" placeholder="New list" class="border rounded border-neutral-400 text-sm px-2 py-1 bg-black" ;
+                    div #new-list-error class="text-sm text-red-500" {}
+                }
+                a href="/tags" class="text-sm text-neutral-400" { "Manage tags" }
+                a href="/archive" class="text-sm text-neutral-400" { "Archive" }
+                a href="/import" class="text-sm text-neutral-400" { "Import" }
+            }
+            main class="flex-1 flex flex-col gap-4" hx-ext="sse" sse-connect=(format!("{}/events", list.url())) {
+                (events::render_listener())
+                (undo::render_empty())
+                (render_header(&list))
+                form
+                class="flex flex-row gap-4"
+                hx-post=(format!("{}/add", list.url()))
+                hx-target="#todo-list"
+                hx-swap="beforeend" {
+                    input name="prompt" placeholder="New todo, add tags with #tag" class="flex-1 border rounded border-neutral-400 text-sm px-4 py-2 bg-black" ;
+                    input name="due" type="date" title="Due date" class="border rounded border-neutral-400 text-sm px-4 py-2 bg-black" ;
+                    (Priority::render_select(Priority::Normal))
+                    (Recurrence::render_input(""))
+                    button class="rounded bg-blue-500 px-4 py-2" {"Add"}
+                }
+                datalist #repeat-rules {
+                    @for example in Recurrence::EXAMPLES {
+                        option value=(example) {}
+                    }
+                }
+                (render_filters(&list, &query, false))
+                div class="flex flex-row gap-4" {
+                    div ."flex-1" ."text-neutral-400" hx-get=(format!("{}/statistic", list.url())) hx-include="#filter" hx-trigger="changedTodos from:body, sse:patch, sse:reload"{
+                        (render_statistic(&todos, query.filter, false))
+                    }
+                    button class="text-sm text-neutral-400" hx-post=(format!("{}/archive-completed", list.url())) hx-include="#filter, #sort, #tag" hx-target="#todo-list" {"Archive completed"}
+                    a href=(format!("{}/history", list.url())) class="text-sm text-neutral-400" { "History" }
+                }
+                input #search type="search" name="q" placeholder="Search"
+                class="border rounded border-neutral-400 text-sm px-4 py-2 bg-black"
+                hx-get=(format!("{}/search", list.url()))
+                hx-trigger="keyup changed delay:300ms, search"
+                hx-target="#todo-list"
+                hx-include="#filter, #sort, #tag" ;
+                ul #todo-list hx-get=(format!("{}/items", list.url())) hx-include="#filter, #sort, #tag, #search" hx-trigger="sse:reload" data-reorder=(format!("{}/reorder", list.url())) {
+                    (render_list(&todos, &query))
+                }
+            }
+        }
+        }
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body.into_string()))
+}
+
+#[derive(Deserialize)]
+struct ListForm {
+    title: String,
+}
+
+#[post("/lists")]
+async fn create(
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+    form: web::Form<ListForm>,
+) -> impl Responder {
+    let mut state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let title = match todo::validate_name(&form.title) {
+        Ok(title) => title,
+        Err(error) => return Ok(HttpResponse::Ok().body(error)),
+    };
+    let list = state.store.create_list(user.0, &title)?;
+    Ok(HttpResponse::Ok()
+        .append_header(("HX-Redirect", list.url()))
+        .finish())
+}
+
+#[get("/header")]
+async fn header(req: HttpRequest, data: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    match state.store.get_list(path_id(&req, "list_id")?)? {
+        Some(list) => Ok(HttpResponse::Ok().body(render_header(&list).into_string())),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+#[get("/rename")]
+async fn rename_form(req: HttpRequest, data: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    match state.store.get_list(path_id(&req, "list_id")?)? {
+        Some(list) => {
+            Ok(HttpResponse::Ok().body(render_rename_form(&list, &list.title, None).into_string()))
+        }
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+#[post("/rename")]
+async fn rename(
+    req: HttpRequest,
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+    form: web::Form<ListForm>,
+) -> impl Responder {
+    let mut state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let Some(mut list) = state.store.get_list(path_id(&req, "list_id")?)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    match todo::validate_name(&form.title) {
+        Ok(title) => {
+            list.title = title;
+            state.store.update_list(&list)?;
+            let lists = state.store.lists(Some(user.0))?;
+            // the sidebar shows the title as well, so it is swapped out of band
+            let body = html! {
+                (render_header(&list))
+                (render_sidebar(&lists, list.id, true))
+            };
+            Ok(HttpResponse::Ok().body(body.into_string()))
+        }
+        Err(error) => Ok(HttpResponse::Ok()
+            .body(render_rename_form(&list, &form.title, Some(error)).into_string())),
+    }
+}
+
+#[delete("")]
+async fn remove(req: HttpRequest, data: web::Data<Mutex<AppState>>) -> impl Responder {
+    let mut state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let list_id = path_id(&req, "list_id")?;
+    if state.store.remove_list(list_id)? {
+        // other tabs of the list reload it and are sent to the start page
+        state.events.send(TodoEvent::ListChanged(list_id));
+        return Ok(HttpResponse::Ok()
+            .append_header(("HX-Redirect", "/"))
+            .finish());
+    }
+    Ok(HttpResponse::NotFound().finish())
+}
@@ -1,36 +1,44 @@
-use std::{ops::Deref, sync::Mutex};
+mod api;
+mod archive;
+mod audit;
+mod auth;
+mod caldav;
+mod daily;
+mod events;
+mod export;
+mod ical;
+mod import;
+mod lists;
+mod search;
+mod store;
+mod tags;
+mod todo;
+mod todotxt;
+mod undo;
+
+use std::sync::Mutex;
 
 use actix_files as fs;
+use actix_session::Session;
 use actix_web::{
-    error, get, post, web, App, HttpRequest, HttpResponse, HttpServer, Responder, Result,
+    delete, error, get, middleware, post, web, App, HttpRequest, HttpResponse, HttpServer,
+    Responder, Result,
 };
+use audit::Action;
+use auth::CurrentUser;
 use derive_more::{Display, Error};
-use maud::{html, Markup, DOCTYPE};
-use serde::Deserialize;
-
-#[derive(Debug, Deserialize, Clone)]
-struct Todo {
-    id: u128,
-    name: String,
-    done: bool,
-}
-
-impl Todo {
-    fn render(&self) -> Markup {
-        let id = format!("todo-{}", self.id);
-        html!(
-            li id=(id) class="flex flex-row"{
-                div .line-through[self.done] ."flex-1" {
-                    (self.name)
-                }
-                input type="checkbox" checked[self.done] hx-post=(format!("/{}/done", self.id)) hx-trigger="click" hx-target=(format!("#{}", id)) hx-swap="outerHTML" ;
-            }
-        )
-    }
-}
+use events::{Broadcaster, TodoEvent};
+use maud::{html, Markup};
+use search::SearchQuery;
+use serde::{de, Deserialize, Deserializer};
+use store::{StoreError, TodoStore};
+use todo::{EditForm, Filter, Priority, Recurrence, Sort, Todo, TodoList};
+use undo::{Change, UndoLog};
+
 struct AppState {
-    todos: Vec<Todo>,
-    last_index: u128,
+    store: Box<dyn TodoStore>,
+    events: Broadcaster,
+    undo: UndoLog,
 }
 
 #[derive(Debug, Display, Error)]
@@ -41,62 +49,279 @@ struct ApiError {
 
 impl error::ResponseError for ApiError {}
 
-fn render_list(todos: &[Todo]) -> Markup {
+impl From<StoreError> for ApiError {
+    fn from(err: StoreError) -> Self {
+        eprintln!("storage error: {}", err);
+        ApiError { name: "storage" }
+    }
+}
+
+/// Reads an id from the path variable `name`, e.g. `id` for `{id}`.
+fn path_id(req: &HttpRequest, name: &str) -> Result<u128, ApiError> {
+    match req.match_info().get(name).map(str::parse) {
+        Some(Ok(id)) => Ok(id),
+        _ => Err(ApiError {
+            name: "path variable",
+        }),
+    }
+}
+
+/// Reads an optional id from the query string, for fields with
+/// `#[serde(default, deserialize_with = "query_id")]`. Query strings cannot be
+/// parsed into `u128` directly, so the id is read as text.
+fn query_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u128>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(id) => id.parse().map(Some).map_err(de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+/// Checks that the list exists and belongs to the user.
+fn owns_list(store: &dyn TodoStore, user: CurrentUser, list_id: u128) -> Result<bool, ApiError> {
+    Ok(store
+        .get_list(list_id)?
+        .is_some_and(|list| list.user_id == Some(user.0)))
+}
+
+/// Loads the todo of the `{id}` path variable if it belongs to the list of the
+/// `{list_id}` path variable and is not archived.
+fn find_todo(store: &dyn TodoStore, req: &HttpRequest) -> Result<Option<Todo>, ApiError> {
+    let list_id = path_id(req, "list_id")?;
+    Ok(store
+        .get(path_id(req, "id")?)?
+        .filter(|todo| todo.list_id == list_id && !todo.archived))
+}
+
+/// How the todos of a list are shown.
+#[derive(Deserialize, Default, Clone)]
+struct ViewQuery {
+    #[serde(default)]
+    filter: Filter,
+    #[serde(default)]
+    sort: Sort,
+    /// Only todos with this tag are shown, unless it is empty.
+    #[serde(default)]
+    tag: String,
+}
+
+impl ViewQuery {
+    fn matches(&self, todo: &Todo) -> bool {
+        self.filter.matches(todo) && (self.tag.is_empty() || todo.has_tag(&self.tag))
+    }
+
+    /// The query string without the default values, e.g. `?filter=active`.
+    fn query_string(&self) -> String {
+        let mut params = form_urlencoded::Serializer::new(String::new());
+        if self.filter != Filter::All {
+            params.append_pair("filter", self.filter.as_str());
+        }
+        if self.sort != Sort::Manual {
+            params.append_pair("sort", self.sort.as_str());
+        }
+        if !self.tag.is_empty() {
+            params.append_pair("tag", &self.tag);
+        }
+        match params.finish() {
+            params if params.is_empty() => String::new(),
+            params => format!("?{}", params),
+        }
+    }
+}
+
+/// Reads the view of a POST request. htmx sends it with
+/// `hx-include="#filter, #sort, #tag"`.
+fn posted_view(form: Option<web::Form<ViewQuery>>) -> ViewQuery {
+    form.map(web::Form::into_inner).unwrap_or_default()
+}
+
+/// Path and query of the page which sent the htmx request.
+fn current_url(req: &HttpRequest) -> Option<&str> {
+    let url = req.headers().get("HX-Current-URL")?.to_str().ok()?;
+    let (_, rest) = url.split_once("://")?;
+    rest.find('/').map(|index| &rest[index..])
+}
+
+/// Renders the todos which match the view. The view applies to the top-level
+/// todos, which are shown with all of their subtasks.
+fn render_list(todos: &[Todo], view: &ViewQuery) -> Markup {
+    let mut top: Vec<Todo> = todos
+        .iter()
+        .filter(|todo| todo.parent_id.is_none() && view.matches(todo))
+        .cloned()
+        .collect();
+    view.sort.apply(&mut top);
     html! {
-        @for todo in todos.iter() {
-            (todo.render())
+        @for todo in top.iter() {
+            (todo.render(&todo::subtasks(todos, todo.id)))
         }
     }
 }
 
-#[get("/")]
-async fn index(data: web::Data<Mutex<AppState>>) -> Result<Markup, ApiError> {
-    let state = match data.lock() {
-        Ok(state) => state,
-        Err(_) => return Err(ApiError { name: "mutex lock" }),
+/// Renders the todo with its current subtasks.
+fn render_todo(store: &dyn TodoStore, todo: &Todo) -> Result<Markup, ApiError> {
+    let todos = store.list(Some(todo.list_id))?;
+    Ok(todo.render(&todo::subtasks(&todos, todo.id)))
+}
+
+/// Adds the next occurrence of a completed recurring todo, with open copies of
+/// its subtasks. Nothing is added if the list already has the occurrence, e.g.
+/// because the todo was completed, reopened and completed again.
+fn schedule_next(
+    state: &mut AppState,
+    user_id: Option<u128>,
+    todo: &Todo,
+) -> Result<Option<Todo>, StoreError> {
+    let Some(next) = todo.next_occurrence(todo::today()) else {
+        return Ok(None);
     };
-    let body = html! {
-        (DOCTYPE)
-        script src="/assets/tailwind.min.js" {}
-        script src="/assets/htmx.min.js"{}
-        link rel="icon" type="image/png" href="/assets/favicon.png";
-        link src="/assets/global.css" rel="stylesheet" {}
-        title {
-            "Todo"
-        }
-
-        body ."min-h-sreen" .text-white .bg-black ."p-4" {
-        main class="container m-auto flex flex-col gap-4" {
-            h1 class="text-2xl" {
-                "Daily todos"
-            }
-            form
-            class="flex flex-row gap-4"
-            hx-post="/add"
-            hx-target="#todo-list"
-            hx-swap="beforeend" {
-                input name="prompt" class="flex-1 border rounded border-neutral-400 text-sm px-4 py-2 bg-black" ;
-                button class="rounded bg-blue-500 px-4 py-2" {"Add"}
+    let todos = state.store.list(Some(todo.list_id))?;
+    let exists = todos.iter().any(|other| {
+        !other.done
+            && other.parent_id == next.parent_id
+            && other.name == next.name
+            && other.due == next.due
+            && other.recurrence == next.recurrence
+    });
+    if exists {
+        return Ok(None);
+    }
+    let next = state.store.create(next)?;
+    audit::created(state, user_id, &next)?;
+    // due subtasks keep their distance to the due date of the todo
+    let shift = todo.due.zip(next.due).map(|(due, next_due)| next_due - due);
+    for subtask in todo::subtasks(&todos, todo.id) {
+        let copy = state.store.create(Todo {
+            parent_id: Some(next.id),
+            done: false,
+            created: None,
+            completed: None,
+            uid: None,
+            resource: None,
+            due: match shift {
+                Some(shift) => subtask.due.and_then(|due| due.checked_add_signed(shift)),
+                None => subtask.due,
+            },
+            ..subtask
+        })?;
+        audit::created(state, user_id, &copy)?;
+    }
+    state.events.send(match next.parent_id {
+        Some(_) => TodoEvent::Changed(next.clone()),
+        None => TodoEvent::Added(next.clone()),
+    });
+    Ok(Some(next))
+}
+
+/// Tabs to switch the filter and the order, and the selected tag. They only
+/// swap the list, `/items` pushes the URL. The hidden inputs keep the current
+/// view for the requests which reload parts of the page.
+fn render_filters(list: &TodoList, view: &ViewQuery, oob: bool) -> Markup {
+    let items_url = |view: ViewQuery| format!("{}/items{}", list.url(), view.query_string());
+    html! {
+        nav #filters class="flex flex-row gap-2 text-sm" hx-swap-oob=[oob.then_some("true")] {
+            input #filter type="hidden" name="filter" value=(view.filter.as_str()) ;
+            input #sort type="hidden" name="sort" value=(view.sort.as_str()) ;
+            input #tag type="hidden" name="tag" value=(view.tag) ;
+            @for filter in Filter::ALL {
+                button .rounded ."px-2" ."py-1" .border ."border-neutral-400"[filter != view.filter] .bg-neutral-800[filter == view.filter]
+                hx-get=(items_url(ViewQuery { filter, ..view.clone() }))
+                hx-include="#search"
+                hx-target="#todo-list" {
+                    (filter.label())
+                }
             }
-            div ."text-neutral-400" hx-get="/statistic" hx-trigger="changedTodos from:body"{
-                (format!("Complited {} of {} todos", state.todos.iter().filter(|todo| todo.done).count(), state.todos.len()))
+            @if !view.tag.is_empty() {
+                button class="rounded-full px-2 py-1 bg-neutral-800" title="Show all tags"
+                hx-get=(items_url(ViewQuery { tag: String::new(), ..view.clone() }))
+                hx-include="#search"
+                hx-target="#todo-list" {
+                    "#" (view.tag) " ×"
+                }
             }
-            ul #todo-list {
-                (render_list(state.todos.deref()))
+            span class="flex-1" {}
+            span class="px-2 py-1 text-neutral-400" { "Sort by" }
+            @for sort in Sort::ALL {
+                button .rounded ."px-2" ."py-1" .border ."border-neutral-400"[sort != view.sort] .bg-neutral-800[sort == view.sort]
+                hx-get=(items_url(ViewQuery { sort, ..view.clone() }))
+                hx-include="#search"
+                hx-target="#todo-list" {
+                    (sort.label())
+                }
             }
         }
-        }
+    }
+}
+
+/// Counts the top-level todos like the list shows them, subtasks are left out.
+fn render_statistic(todos: &[Todo], filter: Filter, oob: bool) -> Markup {
+    let todos: Vec<&Todo> = todos
+        .iter()
+        .filter(|todo| todo.parent_id.is_none())
+        .collect();
+    let done = todos.iter().filter(|todo| todo.done).count();
+    let today = todo::today();
+    let overdue = todos.iter().filter(|todo| todo.is_overdue(today)).count();
+    let open: Vec<(Priority, usize)> = Priority::ALL
+        .into_iter()
+        .map(|priority| {
+            let count = todos
+                .iter()
+                .filter(|todo| !todo.done && todo.priority == priority)
+                .count();
+            (priority, count)
+        })
+        .filter(|(_, count)| *count > 0)
+        .collect();
+    let text = match filter {
+        Filter::All => format!("Complited {} of {} todos", done, todos.len()),
+        Filter::Active => format!("{} active shown of {}", todos.len() - done, todos.len()),
+        Filter::Done => format!("{} completed shown of {}", done, todos.len()),
     };
-    Ok(body)
+    html! {
+        span #statistic hx-swap-oob=[oob.then_some("true")] {
+            (text)
+            @if overdue > 0 {
+                ", " span class="text-red-500" { (overdue) " overdue" }
+            }
+            @if !open.is_empty() {
+                " · open: "
+                @for (index, (priority, count)) in open.iter().enumerate() {
+                    @if index > 0 { ", " }
+                    (count) " " (priority.as_str())
+                }
+            }
+        }
+    }
 }
 
 #[derive(Deserialize)]
 struct FormData {
     prompt: String,
+    #[serde(default)]
+    due: String,
+    #[serde(default)]
+    priority: Priority,
+    #[serde(default)]
+    repeat: String,
+}
+
+/// Renders an error which is added to the list instead of the new item.
+fn render_add_error(error: &str) -> HttpResponse {
+    HttpResponse::Ok().body(
+        html! {
+            div class="bg-red-500"{ (error) }
+        }
+        .into_string(),
+    )
 }
 
 #[post("/add")]
-async fn add(data: web::Data<Mutex<AppState>>, form: web::Form<FormData>) -> impl Responder {
+async fn add(
+    req: HttpRequest,
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+    form: web::Form<FormData>,
+) -> impl Responder {
     let mut state = match data.lock() {
         Ok(val) => val,
         Err(_) => return HttpResponse::Ok().body(
@@ -106,80 +331,513 @@ async fn add(data: web::Data<Mutex<AppState>>, form: web::Form<FormData>) -> imp
             .into_string(),
         ),
     };
-    let id: u128 = state.last_index;
-    let todo = Todo {
-        id,
-        name: form.prompt.clone(),
-        done: false,
+    let (name, tag_names) = todo::parse_prompt(&form.prompt);
+    let name = match todo::validate_name(&name) {
+        Ok(name) => name,
+        Err(error) => return render_add_error(error),
+    };
+    let due = match todo::parse_due(&form.due) {
+        Ok(due) => due,
+        Err(error) => return render_add_error(error),
     };
-    state.todos.push(todo.clone());
-    state.last_index += 1;
+    let recurrence = match Recurrence::parse(&form.repeat) {
+        Ok(recurrence) => recurrence,
+        Err(error) => return render_add_error(error),
+    };
+    let list = match path_id(&req, "list_id").map(|id| state.store.get_list(id)) {
+        Ok(Ok(Some(list))) => list,
+        _ => return HttpResponse::NotFound().finish(),
+    };
+    let created = tags::resolve(&*state.store, user.0, tag_names)
+        .and_then(|tags| {
+            state.store.create(Todo {
+                list_id: list.id,
+                name,
+                due,
+                priority: form.priority,
+                tags,
+                recurrence,
+                ..Todo::default()
+            })
+        })
+        .and_then(|todo| audit::created(&mut state, Some(user.0), &todo).map(|()| todo));
+    let todo = match created {
+        Ok(todo) => todo,
+        Err(err) => {
+            eprintln!("storage error: {}", err);
+            return HttpResponse::Ok().body(
+                html! {
+                    div class="bg-red-500"{ "An error occured while saving the todo" }
+                }
+                .into_string(),
+            );
+        }
+    };
+    state.events.send(TodoEvent::Added(todo.clone()));
     HttpResponse::Ok()
         .append_header(("HX-Trigger", "changedTodos"))
-        .body(todo.render().into_string())
+        .body(todo.render(&[]).into_string())
 }
 
-#[post("{id}/done")]
-async fn toggle_done(req: HttpRequest, data: web::Data<Mutex<AppState>>) -> impl Responder {
+#[post("/{id}/done")]
+async fn toggle_done(
+    req: HttpRequest,
+    user: CurrentUser,
+    session: Session,
+    data: web::Data<Mutex<AppState>>,
+    form: Option<web::Form<ViewQuery>>,
+) -> impl Responder {
     let mut state = match data.lock() {
         Ok(state) => state,
         Err(_) => return Err(ApiError { name: "mutex lock" }),
     };
 
-    let id: u128 = match req.match_info().get("id") {
-        Some(id) => id.parse().unwrap(),
-        None => {
-            return Err(ApiError {
-                name: "path variable",
-            })
+    if let Some(old) = find_todo(&*state.store, &req)? {
+        let mut item = old.clone();
+        item.set_done(!old.done);
+        state.store.update(&item)?;
+        audit::record_changes(&mut state, Some(user.0), &old, &item)?;
+        state.events.send(TodoEvent::Changed(item.clone()));
+        let next = match item.done {
+            true => schedule_next(&mut state, Some(user.0), &item)?,
+            false => None,
+        };
+        let view = posted_view(form);
+        // the item is removed from the page if it does not match the filter
+        // anymore, subtasks are always shown with their parent
+        let mut body = if item.parent_id.is_some() || view.matches(&item) {
+            let todos = state.store.list(Some(item.list_id))?;
+            todo::render_top(&todos, &item, None)
+                .map(Markup::into_string)
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+        // the next occurrence of a subtask is part of the rendered parent
+        if let Some(next) = next
+            .as_ref()
+            .filter(|next| next.parent_id.is_none() && view.matches(next))
+        {
+            body.push_str(
+                &html! {
+                    div hx-swap-oob="beforeend:#todo-list" { (render_todo(&*state.store, next)?) }
+                }
+                .into_string(),
+            );
         }
+        let change = Change::Toggled {
+            id: item.id,
+            done: !item.done,
+            next: next.map(|next| next.id),
+        };
+        let operation = state
+            .undo
+            .record(undo::session_id(&session)?, item.list_id, change);
+        let message = match item.done {
+            true => format!("Completed \"{}\"", item.name),
+            false => format!("Reopened \"{}\"", item.name),
+        };
+        body.push_str(&undo::render_toast(&state.undo, operation, &message).into_string());
+        return Ok(HttpResponse::Ok()
+            .append_header(("HX-Trigger", "changedTodos"))
+            .body(body));
+    }
+    Ok(HttpResponse::NoContent().body(()))
+}
+
+#[get("/{id}")]
+async fn show(req: HttpRequest, data: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
     };
+    match find_todo(&*state.store, &req)? {
+        Some(todo) => Ok(HttpResponse::Ok().body(render_todo(&*state.store, &todo)?.into_string())),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
 
-    let mut todo: Vec<&mut Todo> = state
-        .todos
-        .iter_mut()
-        .filter(|todo| todo.id == id)
-        .collect();
-    if !todo.is_empty() {
-        let item = todo.get_mut(0).unwrap();
-        item.done = !item.done;
+#[get("/{id}/edit")]
+async fn edit_form(req: HttpRequest, data: web::Data<Mutex<AppState>>) -> impl Responder {
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    match find_todo(&*state.store, &req)? {
+        Some(todo) => Ok(
+            HttpResponse::Ok().body(todo.render_edit(&EditForm::new(&todo), None).into_string())
+        ),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+#[post("/{id}/edit")]
+async fn edit(
+    req: HttpRequest,
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+    form: web::Form<EditForm>,
+) -> impl Responder {
+    let mut state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let Some(mut todo) = find_todo(&*state.store, &req)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let parsed = todo::validate_name(&form.name).and_then(|name| {
+        Ok((
+            name,
+            todo::parse_due(&form.due)?,
+            todo::parse_tags(&form.tags)?,
+            Recurrence::parse(&form.repeat)?,
+        ))
+    });
+    match parsed {
+        Ok((name, due, tag_names, recurrence)) => {
+            let old = todo.clone();
+            todo.name = name;
+            todo.due = due;
+            todo.recurrence = recurrence;
+            todo.routine = form.routine;
+            todo.priority = form.priority;
+            todo.tags = tags::resolve(&*state.store, user.0, tag_names)?;
+            state.store.update(&todo)?;
+            audit::record_changes(&mut state, Some(user.0), &old, &todo)?;
+            state.events.send(TodoEvent::Changed(todo.clone()));
+            Ok(HttpResponse::Ok()
+                .append_header(("HX-Trigger", "changedTodos"))
+                .body(render_todo(&*state.store, &todo)?.into_string()))
+        }
+        // htmx only swaps successful responses, so the form with the error is sent with 200
+        Err(error) => {
+            Ok(HttpResponse::Ok().body(todo.render_edit(&form, Some(error)).into_string()))
+        }
+    }
+}
+
+#[delete("/{id}")]
+async fn remove(
+    req: HttpRequest,
+    user: CurrentUser,
+    session: Session,
+    data: web::Data<Mutex<AppState>>,
+) -> impl Responder {
+    let mut state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let Some(todo) = find_todo(&*state.store, &req)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let removed = state.store.with_subtasks(&todo)?;
+    if state.store.remove(todo.id)? {
+        for removed in &removed {
+            audit::record(&mut state, Some(user.0), removed, Action::Deleted)?;
+        }
+        // the empty body replaces the item in the list, a subtask is removed by
+        // rendering its parent again
+        let todos = state.store.list(Some(todo.list_id))?;
+        let mut body = match todo.parent_id {
+            Some(_) => todo::render_top(&todos, &todo, None)
+                .map(Markup::into_string)
+                .unwrap_or_default(),
+            None => String::new(),
+        };
+        let operation = state.undo.record(
+            undo::session_id(&session)?,
+            todo.list_id,
+            Change::Removed(removed),
+        );
+        let message = format!("Deleted \"{}\"", todo.name);
+        body.push_str(&undo::render_toast(&state.undo, operation, &message).into_string());
+        state.events.send(TodoEvent::Removed(todo));
         return Ok(HttpResponse::Ok()
             .append_header(("HX-Trigger", "changedTodos"))
-            .body(item.render().into_string()));
+            .body(body));
     }
-    Ok(HttpResponse::NoContent().body(()))
+    Ok(HttpResponse::NotFound().finish())
 }
 
-#[get("/statistic")]
-async fn render_stats(data: web::Data<Mutex<AppState>>) -> impl Responder {
+#[get("/{id}/subtask")]
+async fn subtask_form(req: HttpRequest, data: web::Data<Mutex<AppState>>) -> impl Responder {
     let state = match data.lock() {
         Ok(state) => state,
         Err(_) => return Err(ApiError { name: "mutex lock" }),
     };
+    match find_todo(&*state.store, &req)? {
+        Some(todo) if todo.parent_id.is_none() => {
+            Ok(HttpResponse::Ok().body(todo.render_subtask_form("", None).into_string()))
+        }
+        _ => Ok(HttpResponse::NotFound().finish()),
+    }
+}
 
-    Ok(html! {
-        span {
-            (format!("Complited {} of {} todos", state.todos.iter().filter(|todo| todo.done).count(), state.todos.len()))
+/// Adds a subtask and renders the parent with all of its subtasks.
+#[post("/{id}/subtasks")]
+async fn add_subtask(
+    req: HttpRequest,
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+    form: web::Form<FormData>,
+) -> impl Responder {
+    let mut state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let parent = match find_todo(&*state.store, &req)? {
+        Some(todo) if todo.parent_id.is_none() => todo,
+        _ => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let (name, tag_names) = todo::parse_prompt(&form.prompt);
+    let name = match todo::validate_name(&name) {
+        Ok(name) => name,
+        // the error is shown in the form instead of replacing the parent
+        Err(error) => {
+            return Ok(HttpResponse::Ok()
+                .append_header(("HX-Retarget", format!("#subtask-form-{}", parent.id)))
+                .body(
+                    parent
+                        .render_subtask_form(&form.prompt, Some(error))
+                        .into_string(),
+                ))
         }
-    })
+    };
+    let tags = tags::resolve(&*state.store, user.0, tag_names)?;
+    let subtask = state.store.create(Todo {
+        list_id: parent.list_id,
+        parent_id: Some(parent.id),
+        name,
+        priority: form.priority,
+        tags,
+        ..Todo::default()
+    })?;
+    audit::created(&mut state, Some(user.0), &subtask)?;
+    state.events.send(TodoEvent::Changed(subtask));
+    Ok(HttpResponse::Ok()
+        .append_header(("HX-Trigger", "changedTodos"))
+        .body(render_todo(&*state.store, &parent)?.into_string()))
+}
+
+/// Completes all open subtasks of the todo.
+#[post("/{id}/complete-subtasks")]
+async fn complete_subtasks(
+    req: HttpRequest,
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+) -> impl Responder {
+    let mut state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let Some(parent) = find_todo(&*state.store, &req)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let todos = state.store.list(Some(parent.list_id))?;
+    for mut subtask in todo::subtasks(&todos, parent.id) {
+        if !subtask.done {
+            subtask.set_done(true);
+            state.store.update(&subtask)?;
+            audit::record(&mut state, Some(user.0), &subtask, Action::Completed)?;
+            state.events.send(TodoEvent::Changed(subtask));
+        }
+    }
+    Ok(HttpResponse::Ok()
+        .append_header(("HX-Trigger", "changedTodos"))
+        .body(render_todo(&*state.store, &parent)?.into_string()))
+}
+
+#[derive(Deserialize)]
+struct ReorderForm {
+    /// Comma separated ids in the new order.
+    order: String,
+}
+
+/// Stores the order after a todo was dragged. Only the posted todos move, see
+/// [`TodoStore::reorder`].
+#[post("/reorder")]
+async fn reorder(
+    req: HttpRequest,
+    data: web::Data<Mutex<AppState>>,
+    form: web::Form<ReorderForm>,
+) -> impl Responder {
+    let mut state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let list_id = path_id(&req, "list_id")?;
+    let ids: Result<Vec<u128>, _> = form
+        .order
+        .split(',')
+        .filter(|id| !id.is_empty())
+        .map(str::parse)
+        .collect();
+    let Ok(ids) = ids else {
+        return Ok(HttpResponse::BadRequest().finish());
+    };
+    if !state.store.reorder(list_id, &ids)? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    state.events.send(TodoEvent::ListChanged(list_id));
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/archive-completed")]
+async fn archive_completed(
+    req: HttpRequest,
+    user: CurrentUser,
+    session: Session,
+    data: web::Data<Mutex<AppState>>,
+    form: Option<web::Form<ViewQuery>>,
+) -> impl Responder {
+    let mut state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let view = posted_view(form);
+    let list_id = path_id(&req, "list_id")?;
+    let archived = state.store.archive_done(list_id)?;
+    state.events.send(TodoEvent::ListChanged(list_id));
+    for todo in &archived {
+        audit::record(&mut state, Some(user.0), todo, Action::Archived)?;
+    }
+    let todos = state.store.list(Some(list_id))?;
+    let mut body = render_list(&todos, &view).into_string();
+    if !archived.is_empty() {
+        let message = match archived.len() {
+            1 => "Archived 1 todo".to_string(),
+            count => format!("Archived {} todos", count),
+        };
+        let ids = archived.iter().map(|todo| todo.id).collect();
+        let operation =
+            state
+                .undo
+                .record(undo::session_id(&session)?, list_id, Change::Archived(ids));
+        body.push_str(&undo::render_toast(&state.undo, operation, &message).into_string());
+    }
+    Ok(HttpResponse::Ok()
+        .append_header(("HX-Trigger", "changedTodos"))
+        .body(body))
+}
+
+/// The items of the list, limited by the view and the search query. The
+/// filter tabs and the statistic are updated out of band, because they depend
+/// on the view as well. If the view changed, the URL of the page is updated.
+#[get("/items")]
+async fn items(
+    req: HttpRequest,
+    data: web::Data<Mutex<AppState>>,
+    query: web::Query<SearchQuery>,
+) -> impl Responder {
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let Some(list) = state.store.get_list(path_id(&req, "list_id")?)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let todos = state.store.list(Some(list.id))?;
+    let body = html! {
+        (search::render_results(&state, list.id, &query)?)
+        (render_filters(&list, &query.view, true))
+        (render_statistic(&todos, query.view.filter, true))
+    };
+    let url = format!("{}{}", list.url(), query.view.query_string());
+    let mut response = HttpResponse::Ok();
+    if current_url(&req) != Some(url.as_str()) {
+        response.append_header(("HX-Push-Url", url));
+    }
+    Ok(response.body(body.into_string()))
+}
+
+#[get("/statistic")]
+async fn render_stats(
+    req: HttpRequest,
+    data: web::Data<Mutex<AppState>>,
+    query: web::Query<ViewQuery>,
+) -> impl Responder {
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let todos = state.store.list(Some(path_id(&req, "list_id")?))?;
+
+    Ok(render_statistic(&todos, query.filter, false))
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let port = 8080;
 
+    let store = store::from_env().map_err(|err| std::io::Error::other(err.to_string()))?;
+    let session_key = auth::session_key()?;
+    let reset_time = daily::reset_time()?;
+    let undo_time = undo::undo_time()?;
+    let todotxt = todotxt::sync_config()?;
     let data = web::Data::new(Mutex::new(AppState {
-        todos: vec![],
-        last_index: 0,
+        store,
+        events: Broadcaster::default(),
+        undo: UndoLog::new(undo_time),
     }));
+    actix_web::rt::spawn(daily::run(web::Data::clone(&data), reset_time));
+    if let Some((path, list_id)) = todotxt {
+        let sync = todotxt::Sync::new(path, list_id);
+        actix_web::rt::spawn(todotxt::run(web::Data::clone(&data), sync));
+    }
     let server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::clone(&data))
+            .wrap(middleware::from_fn(auth::require_login))
+            .wrap(auth::session_middleware(session_key.clone()))
             .service(fs::Files::new("/assets", "./static").show_files_listing())
-            .service(index)
-            .service(add)
-            .service(toggle_done)
-            .service(render_stats)
+            .service(api::scope())
+            .configure(caldav::services)
+            .service(auth::login_form)
+            .service(auth::login)
+            .service(auth::register_form)
+            .service(auth::register)
+            .service(auth::logout)
+            .service(lists::index)
+            .service(lists::create)
+            .service(tags::page)
+            .service(tags::update)
+            .service(tags::merge)
+            .service(archive::page)
+            .service(archive::items)
+            .service(archive::restore)
+            .service(archive::remove)
+            .service(export::export)
+            .service(export::feed)
+            .service(import::page)
+            .service(import::upload)
+            .service(undo::undo)
+            .service(undo::dismiss)
+            .service(
+                web::scope("/lists/{list_id}")
+                    .wrap(middleware::from_fn(lists::require_owner))
+                    .service(lists::show)
+                    .service(lists::rename_form)
+                    .service(lists::rename)
+                    .service(lists::header)
+                    .service(lists::remove)
+                    .service(add)
+                    .service(toggle_done)
+                    .service(render_stats)
+                    .service(items)
+                    .service(search::search)
+                    .service(events::stream_events)
+                    .service(archive_completed)
+                    .service(reorder)
+                    .service(daily::history)
+                    .service(edit_form)
+                    .service(edit)
+                    .service(audit::history)
+                    .service(subtask_form)
+                    .service(add_subtask)
+                    .service(complete_subtasks)
+                    .service(show)
+                    .service(remove),
+            )
     })
     .bind(("127.0.0.1", port))?
     .run();
@@ -0,0 +1,125 @@
+//! Case-insensitive search in the todo names with highlighting of the matches.
+
+use std::sync::Mutex;
+
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use maud::{html, Markup};
+use serde::Deserialize;
+
+use crate::{
+    path_id,
+    todo::{self, Todo},
+    ApiError, AppState, ViewQuery,
+};
+
+/// Returns the byte ranges of all non-overlapping matches of `query` in
+/// `text`. Both are compared in lowercase, which also works for characters
+/// whose lowercase form has a different length.
+pub fn find_matches(text: &str, query: &str) -> Vec<(usize, usize)> {
+    let query: Vec<char> = query.trim().chars().flat_map(char::to_lowercase).collect();
+    if query.is_empty() {
+        return vec![];
+    }
+    // every lowercase char remembers the byte range of its original char
+    let mut folded = vec![];
+    for (start, c) in text.char_indices() {
+        for lower in c.to_lowercase() {
+            folded.push((lower, start, start + c.len_utf8()));
+        }
+    }
+    let mut ranges = vec![];
+    let mut i = 0;
+    while i + query.len() <= folded.len() {
+        let window = &folded[i..i + query.len()];
+        if window.iter().map(|(c, _, _)| *c).eq(query.iter().copied()) {
+            ranges.push((window[0].1, window[query.len() - 1].2));
+            i += query.len();
+        } else {
+            i += 1;
+        }
+    }
+    ranges
+}
+
+pub fn matches(text: &str, query: &str) -> bool {
+    !find_matches(text, query).is_empty()
+}
+
+/// Renders `text` with all matches of `query` wrapped in `mark`.
+pub fn highlight(text: &str, query: &str) -> Markup {
+    let mut parts = vec![];
+    let mut last = 0;
+    for (start, end) in find_matches(text, query) {
+        parts.push((&text[last..start], false));
+        parts.push((&text[start..end], true));
+        last = end;
+    }
+    parts.push((&text[last..], false));
+    html! {
+        @for (part, matched) in parts {
+            @if matched {
+                mark class="bg-yellow-300 text-black" { (part) }
+            } @else {
+                (part)
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    #[serde(default)]
+    pub q: String,
+    #[serde(flatten)]
+    pub view: ViewQuery,
+}
+
+/// Renders the todos of the list which match the query and the filter. An
+/// empty query shows all todos. Todos are shown with all of their subtasks,
+/// also if only a subtask matches.
+pub fn render_results(
+    state: &AppState,
+    list_id: u128,
+    query: &SearchQuery,
+) -> Result<Markup, ApiError> {
+    let q = query.q.trim();
+    let all = state.store.list(Some(list_id))?;
+    // a matching subtask shows its parent
+    let hits: Vec<u128> = if q.is_empty() {
+        all.iter().map(|todo| todo.id).collect()
+    } else {
+        state
+            .store
+            .search(list_id, q)?
+            .iter()
+            .map(|todo| todo.parent_id.unwrap_or(todo.id))
+            .collect()
+    };
+    let mut todos: Vec<Todo> = all
+        .iter()
+        .filter(|todo| {
+            todo.parent_id.is_none() && hits.contains(&todo.id) && query.view.matches(todo)
+        })
+        .cloned()
+        .collect();
+    query.view.sort.apply(&mut todos);
+    Ok(html! {
+        @for todo in &todos {
+            (todo.render_match(&todo::subtasks(&all, todo.id), q))
+        }
+    })
+}
+
+#[get("/search")]
+async fn search(
+    req: HttpRequest,
+    data: web::Data<Mutex<AppState>>,
+    query: web::Query<SearchQuery>,
+) -> impl Responder {
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let results = render_results(&state, path_id(&req, "list_id")?, &query)?;
+    Ok(HttpResponse::Ok().body(results.into_string()))
+}
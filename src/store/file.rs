@@ -0,0 +1,141 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use super::{MemoryStore, StoreError, TodoStore};
+use crate::{
+    audit::AuditEntry,
+    auth::User,
+    daily::DayRecord,
+    todo::{Todo, TodoList},
+};
+
+/// Keeps the todos in memory and writes the whole state as JSON to a file
+/// after every change.
+pub struct FileStore {
+    path: PathBuf,
+    inner: MemoryStore,
+}
+
+impl FileStore {
+    /// Loads the store from `path`. A missing file starts with an empty list.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let path = path.as_ref().to_path_buf();
+        let inner = match fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => MemoryStore::default(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(FileStore { path, inner })
+    }
+
+    fn save(&self) -> Result<(), StoreError> {
+        // write to a temporary file first so a crash never leaves a half written file
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&self.inner)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Runs `change` on the in-memory state and saves the file if `changed`
+    /// says the result modified something.
+    fn change<T>(
+        &mut self,
+        change: impl FnOnce(&mut MemoryStore) -> Result<T, StoreError>,
+        changed: impl FnOnce(&T) -> bool,
+    ) -> Result<T, StoreError> {
+        let result = change(&mut self.inner)?;
+        if changed(&result) {
+            self.save()?;
+        }
+        Ok(result)
+    }
+}
+
+impl TodoStore for FileStore {
+    fn get_user(&self, id: u128) -> Result<Option<User>, StoreError> {
+        self.inner.get_user(id)
+    }
+
+    fn has_users(&self) -> Result<bool, StoreError> {
+        self.inner.has_users()
+    }
+
+    fn find_user(&self, name: &str) -> Result<Option<User>, StoreError> {
+        self.inner.find_user(name)
+    }
+
+    fn create_user(&mut self, name: &str, password_hash: &str) -> Result<User, StoreError> {
+        self.change(|inner| inner.create_user(name, password_hash), |_| true)
+    }
+
+    fn adopt_lists(&mut self, user_id: u128) -> Result<usize, StoreError> {
+        self.change(|inner| inner.adopt_lists(user_id), |adopted| *adopted > 0)
+    }
+
+    fn lists(&self, user_id: Option<u128>) -> Result<Vec<TodoList>, StoreError> {
+        self.inner.lists(user_id)
+    }
+
+    fn get_list(&self, id: u128) -> Result<Option<TodoList>, StoreError> {
+        self.inner.get_list(id)
+    }
+
+    fn create_list(&mut self, user_id: u128, title: &str) -> Result<TodoList, StoreError> {
+        self.change(|inner| inner.create_list(user_id, title), |_| true)
+    }
+
+    fn update_list(&mut self, list: &TodoList) -> Result<bool, StoreError> {
+        self.change(|inner| inner.update_list(list), |found| *found)
+    }
+
+    fn remove_list(&mut self, id: u128) -> Result<bool, StoreError> {
+        self.change(|inner| inner.remove_list(id), |found| *found)
+    }
+
+    fn list(&self, list_id: Option<u128>) -> Result<Vec<Todo>, StoreError> {
+        self.inner.list(list_id)
+    }
+
+    fn archived(&self, list_id: u128) -> Result<Vec<Todo>, StoreError> {
+        self.inner.archived(list_id)
+    }
+
+    fn get(&self, id: u128) -> Result<Option<Todo>, StoreError> {
+        self.inner.get(id)
+    }
+
+    fn create(&mut self, todo: Todo) -> Result<Todo, StoreError> {
+        self.change(|inner| inner.create(todo), |_| true)
+    }
+
+    fn update(&mut self, todo: &Todo) -> Result<bool, StoreError> {
+        self.change(|inner| inner.update(todo), |found| *found)
+    }
+
+    fn restore(&mut self, todo: &Todo) -> Result<(), StoreError> {
+        self.change(|inner| inner.restore(todo), |_| true)
+    }
+
+    fn remove(&mut self, id: u128) -> Result<bool, StoreError> {
+        self.change(|inner| inner.remove(id), |found| *found)
+    }
+
+    fn add_day(&mut self, record: &DayRecord) -> Result<(), StoreError> {
+        self.change(|inner| inner.add_day(record), |_| true)
+    }
+
+    fn days(&self, list_id: u128) -> Result<Vec<DayRecord>, StoreError> {
+        self.inner.days(list_id)
+    }
+
+    fn append_audit(&mut self, entry: &AuditEntry) -> Result<(), StoreError> {
+        self.change(|inner| inner.append_audit(entry), |_| true)
+    }
+
+    fn audit(&self, todo_id: u128) -> Result<Vec<AuditEntry>, StoreError> {
+        self.inner.audit(todo_id)
+    }
+}
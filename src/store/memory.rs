@@ -0,0 +1,207 @@
+use serde::{Deserialize, Serialize};
+
+use super::{StoreError, TodoStore};
+use crate::{
+    audit::AuditEntry,
+    auth::User,
+    daily::DayRecord,
+    todo::{self, Todo, TodoList},
+};
+
+/// Keeps the todos only in memory. Everything is lost on restart.
+#[derive(Default, Serialize, Deserialize)]
+pub struct MemoryStore {
+    #[serde(default)]
+    users: Vec<User>,
+    #[serde(default)]
+    last_user_index: u128,
+    // files written before there were multiple lists have no lists at all
+    #[serde(default)]
+    lists: Vec<TodoList>,
+    #[serde(default)]
+    last_list_index: u128,
+    todos: Vec<Todo>,
+    last_index: u128,
+    #[serde(default)]
+    days: Vec<DayRecord>,
+    #[serde(default)]
+    audit: Vec<AuditEntry>,
+}
+
+impl TodoStore for MemoryStore {
+    fn get_user(&self, id: u128) -> Result<Option<User>, StoreError> {
+        Ok(self.users.iter().find(|user| user.id == id).cloned())
+    }
+
+    fn has_users(&self) -> Result<bool, StoreError> {
+        Ok(!self.users.is_empty())
+    }
+
+    fn find_user(&self, name: &str) -> Result<Option<User>, StoreError> {
+        Ok(self
+            .users
+            .iter()
+            .find(|user| user.name.to_lowercase() == name.to_lowercase())
+            .cloned())
+    }
+
+    fn create_user(&mut self, name: &str, password_hash: &str) -> Result<User, StoreError> {
+        let user = User {
+            id: self.last_user_index,
+            name: name.to_lowercase(),
+            password_hash: password_hash.to_string(),
+        };
+        self.users.push(user.clone());
+        self.last_user_index += 1;
+        Ok(user)
+    }
+
+    fn adopt_lists(&mut self, user_id: u128) -> Result<usize, StoreError> {
+        let mut adopted = 0;
+        for list in self.lists.iter_mut().filter(|list| list.user_id.is_none()) {
+            list.user_id = Some(user_id);
+            adopted += 1;
+        }
+        Ok(adopted)
+    }
+
+    fn lists(&self, user_id: Option<u128>) -> Result<Vec<TodoList>, StoreError> {
+        Ok(self
+            .lists
+            .iter()
+            .filter(|list| user_id.is_none() || list.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    fn get_list(&self, id: u128) -> Result<Option<TodoList>, StoreError> {
+        Ok(self.lists.iter().find(|list| list.id == id).cloned())
+    }
+
+    fn create_list(&mut self, user_id: u128, title: &str) -> Result<TodoList, StoreError> {
+        let list = TodoList {
+            id: self.last_list_index,
+            user_id: Some(user_id),
+            title: title.to_string(),
+        };
+        self.lists.push(list.clone());
+        self.last_list_index += 1;
+        Ok(list)
+    }
+
+    fn update_list(&mut self, list: &TodoList) -> Result<bool, StoreError> {
+        match self.lists.iter_mut().find(|item| item.id == list.id) {
+            Some(item) => {
+                *item = list.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn remove_list(&mut self, id: u128) -> Result<bool, StoreError> {
+        let len = self.lists.len();
+        self.lists.retain(|list| list.id != id);
+        if self.lists.len() == len {
+            return Ok(false);
+        }
+        self.todos.retain(|todo| todo.list_id != id);
+        self.days.retain(|day| day.list_id != id);
+        Ok(true)
+    }
+
+    fn list(&self, list_id: Option<u128>) -> Result<Vec<Todo>, StoreError> {
+        let mut todos: Vec<Todo> = self
+            .todos
+            .iter()
+            .filter(|todo| !todo.archived)
+            .filter(|todo| list_id.is_none_or(|list_id| todo.list_id == list_id))
+            .cloned()
+            .collect();
+        // the sort is stable, so the insertion order decides between equal positions
+        todos.sort_by_key(|todo| todo.position);
+        Ok(todos)
+    }
+
+    fn archived(&self, list_id: u128) -> Result<Vec<Todo>, StoreError> {
+        let mut todos: Vec<Todo> = self
+            .todos
+            .iter()
+            .filter(|todo| todo.archived && todo.list_id == list_id)
+            .cloned()
+            .collect();
+        todos.sort_by_key(|todo| todo.position);
+        Ok(todos)
+    }
+
+    fn get(&self, id: u128) -> Result<Option<Todo>, StoreError> {
+        Ok(self.todos.iter().find(|todo| todo.id == id).cloned())
+    }
+
+    fn create(&mut self, mut todo: Todo) -> Result<Todo, StoreError> {
+        todo.id = self.last_index;
+        todo.created = todo.created.or_else(|| Some(todo::today()));
+        todo.position = self
+            .todos
+            .iter()
+            .filter(|item| item.list_id == todo.list_id)
+            .map(|item| item.position)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        self.todos.push(todo.clone());
+        self.last_index += 1;
+        Ok(todo)
+    }
+
+    fn update(&mut self, todo: &Todo) -> Result<bool, StoreError> {
+        match self.todos.iter_mut().find(|item| item.id == todo.id) {
+            Some(item) => {
+                *item = todo.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn restore(&mut self, todo: &Todo) -> Result<(), StoreError> {
+        self.todos.push(todo.clone());
+        Ok(())
+    }
+
+    fn remove(&mut self, id: u128) -> Result<bool, StoreError> {
+        let len = self.todos.len();
+        self.todos
+            .retain(|todo| todo.id != id && todo.parent_id != Some(id));
+        Ok(self.todos.len() != len)
+    }
+
+    fn add_day(&mut self, record: &DayRecord) -> Result<(), StoreError> {
+        self.days.push(record.clone());
+        Ok(())
+    }
+
+    fn days(&self, list_id: u128) -> Result<Vec<DayRecord>, StoreError> {
+        Ok(self
+            .days
+            .iter()
+            .rev()
+            .filter(|day| day.list_id == list_id)
+            .cloned()
+            .collect())
+    }
+
+    fn append_audit(&mut self, entry: &AuditEntry) -> Result<(), StoreError> {
+        self.audit.push(entry.clone());
+        Ok(())
+    }
+
+    fn audit(&self, todo_id: u128) -> Result<Vec<AuditEntry>, StoreError> {
+        Ok(self
+            .audit
+            .iter()
+            .filter(|entry| entry.todo_id == todo_id)
+            .cloned()
+            .collect())
+    }
+}
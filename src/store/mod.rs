@@ -0,0 +1,247 @@
+mod file;
+mod memory;
+mod sqlite;
+
+pub use file::FileStore;
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+use derive_more::{Display, Error, From};
+
+use crate::{
+    audit::AuditEntry,
+    auth::User,
+    daily::DayRecord,
+    search,
+    todo::{Tag, Todo, TodoList},
+};
+
+#[derive(Debug, Display, Error, From)]
+pub enum StoreError {
+    #[display(fmt = "io error: {}", _0)]
+    Io(std::io::Error),
+    #[display(fmt = "format error: {}", _0)]
+    Format(serde_json::Error),
+    #[display(fmt = "database error: {}", _0)]
+    Database(rusqlite::Error),
+    #[display(fmt = "unknown storage backend '{}'", _0)]
+    #[from(ignore)]
+    UnknownBackend(#[error(not(source))] String),
+}
+
+/// Storage backend for the todos. The handlers only talk to this trait, so the
+/// backend can be chosen at startup.
+pub trait TodoStore: Send {
+    fn get_user(&self, id: u128) -> Result<Option<User>, StoreError>;
+    /// Whether any user has registered yet.
+    fn has_users(&self) -> Result<bool, StoreError>;
+    /// Finds a user by name, ignoring the case.
+    fn find_user(&self, name: &str) -> Result<Option<User>, StoreError>;
+    /// Creates a new user and assigns the next free id to it. The name is
+    /// stored in lower case, so all backends compare names the same way. The
+    /// caller has to make sure the name is not taken yet.
+    fn create_user(&mut self, name: &str, password_hash: &str) -> Result<User, StoreError>;
+    /// Assigns all lists without owner to the user and returns how many lists
+    /// were assigned.
+    fn adopt_lists(&mut self, user_id: u128) -> Result<usize, StoreError>;
+
+    /// Returns all lists of the user, or all lists for `None`, in creation
+    /// order.
+    fn lists(&self, user_id: Option<u128>) -> Result<Vec<TodoList>, StoreError>;
+    fn get_list(&self, id: u128) -> Result<Option<TodoList>, StoreError>;
+    /// Creates a new empty list for the user and assigns the next free id to it.
+    fn create_list(&mut self, user_id: u128, title: &str) -> Result<TodoList, StoreError>;
+    /// Replaces the stored list with the same id. Returns `false` if there is
+    /// no such list.
+    fn update_list(&mut self, list: &TodoList) -> Result<bool, StoreError>;
+    /// Removes the list together with all of its todos and its history.
+    /// Returns `false` if there is no such list.
+    fn remove_list(&mut self, id: u128) -> Result<bool, StoreError>;
+
+    /// Returns the todos of the given list, or of all lists for `None`, in
+    /// manual order. Archived todos are left out.
+    fn list(&self, list_id: Option<u128>) -> Result<Vec<Todo>, StoreError>;
+    /// Returns the archived todos of the list in manual order.
+    fn archived(&self, list_id: u128) -> Result<Vec<Todo>, StoreError>;
+    /// Returns any todo, archived or not.
+    fn get(&self, id: u128) -> Result<Option<Todo>, StoreError>;
+    /// Stores a new todo and assigns the next free id to it. The id and the
+    /// position of `todo` are ignored, new todos come last in the manual order.
+    /// Without a creation date the todo is created today.
+    fn create(&mut self, todo: Todo) -> Result<Todo, StoreError>;
+    /// Replaces the stored todo with the same id. Returns `false` if there is
+    /// no such todo.
+    fn update(&mut self, todo: &Todo) -> Result<bool, StoreError>;
+    /// Stores a deleted todo again with its old id and position, e.g. to undo
+    /// the deletion. The caller has to make sure the id is not taken.
+    fn restore(&mut self, todo: &Todo) -> Result<(), StoreError>;
+    /// Removes the todo together with its subtasks. Returns `false` if there is
+    /// no such todo.
+    fn remove(&mut self, id: u128) -> Result<bool, StoreError>;
+
+    /// Appends the completion of a day to the history of its list.
+    fn add_day(&mut self, record: &DayRecord) -> Result<(), StoreError>;
+    /// Returns the history of the list, the latest day first.
+    fn days(&self, list_id: u128) -> Result<Vec<DayRecord>, StoreError>;
+
+    /// Appends an entry to the audit log. Entries are never changed or
+    /// removed, they outlive their todos and lists.
+    fn append_audit(&mut self, entry: &AuditEntry) -> Result<(), StoreError>;
+    /// Returns the audit log of the todo, the oldest entry first.
+    fn audit(&self, todo_id: u128) -> Result<Vec<AuditEntry>, StoreError>;
+
+    /// Archives all completed todos of the list, together with the subtasks of
+    /// completed todos, and returns them.
+    fn archive_done(&mut self, list_id: u128) -> Result<Vec<Todo>, StoreError> {
+        let todos = self.list(Some(list_id))?;
+        let done: Vec<u128> = todos
+            .iter()
+            .filter(|todo| todo.done)
+            .map(|todo| todo.id)
+            .collect();
+        let mut archived = vec![];
+        for mut todo in todos {
+            if done.contains(&todo.id) || todo.parent_id.is_some_and(|id| done.contains(&id)) {
+                todo.archived = true;
+                self.update(&todo)?;
+                archived.push(todo);
+            }
+        }
+        Ok(archived)
+    }
+
+    /// Returns the todo together with its subtasks, archived ones included, as
+    /// they are deleted by [`TodoStore::remove`].
+    fn with_subtasks(&self, todo: &Todo) -> Result<Vec<Todo>, StoreError> {
+        let mut todos = self.list(Some(todo.list_id))?;
+        todos.extend(self.archived(todo.list_id)?);
+        todos.retain(|item| item.id == todo.id || item.parent_id == Some(todo.id));
+        Ok(todos)
+    }
+
+    /// Returns the todos of the list whose name contains the query, ignoring
+    /// the case. Archived todos are left out. The default implementation scans
+    /// all todos of the list.
+    fn search(&self, list_id: u128, query: &str) -> Result<Vec<Todo>, StoreError> {
+        let mut todos = self.list(Some(list_id))?;
+        todos.retain(|todo| search::matches(&todo.name, query));
+        Ok(todos)
+    }
+
+    /// Moves the given todos of the list into the given order. The other todos
+    /// keep their places, so a filtered view or the subtasks of one todo can be
+    /// reordered on their own. Returns `false` if a todo is not in the list.
+    fn reorder(&mut self, list_id: u128, ids: &[u128]) -> Result<bool, StoreError> {
+        let todos = self.list(Some(list_id))?;
+        let mut moved = vec![];
+        for id in ids {
+            match todos.iter().find(|todo| todo.id == *id) {
+                Some(todo) => moved.push(todo.clone()),
+                None => return Ok(false),
+            }
+        }
+        let mut moved = moved.into_iter();
+        for (index, todo) in todos.iter().enumerate() {
+            let mut todo = match ids.contains(&todo.id) {
+                true => moved.next().unwrap_or_else(|| todo.clone()),
+                false => todo.clone(),
+            };
+            let position = index as i64 + 1;
+            if todo.position != position {
+                todo.position = position;
+                self.update(&todo)?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Returns the tags used in the lists of the user, ordered by name, with
+    /// the number of todos which have them. Archived todos count as well.
+    fn tags(&self, user_id: u128) -> Result<Vec<(Tag, usize)>, StoreError> {
+        let mut tags: Vec<(Tag, usize)> = vec![];
+        for list in self.lists(Some(user_id))? {
+            for todo in self
+                .list(Some(list.id))?
+                .into_iter()
+                .chain(self.archived(list.id)?)
+            {
+                for tag in todo.tags {
+                    match tags.iter_mut().find(|(known, _)| known.name == tag.name) {
+                        Some((_, count)) => *count += 1,
+                        None => tags.push((tag, 1)),
+                    }
+                }
+            }
+        }
+        tags.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+        Ok(tags)
+    }
+
+    /// Replaces the tag `name` as well as the tag with the new name by `tag` on
+    /// all todos of the user, so renaming a tag to the name of another tag
+    /// merges both. Returns the changed todos.
+    fn update_tag(
+        &mut self,
+        user_id: u128,
+        name: &str,
+        tag: &Tag,
+    ) -> Result<Vec<Todo>, StoreError> {
+        let mut changed = vec![];
+        for list in self.lists(Some(user_id))? {
+            for mut todo in self
+                .list(Some(list.id))?
+                .into_iter()
+                .chain(self.archived(list.id)?)
+            {
+                if !todo.has_tag(name) && !todo.has_tag(&tag.name) {
+                    continue;
+                }
+                let mut tags: Vec<Tag> = vec![];
+                for old in todo.tags {
+                    let new = match old.name == name || old.name == tag.name {
+                        true => tag.clone(),
+                        false => old,
+                    };
+                    if !tags.contains(&new) {
+                        tags.push(new);
+                    }
+                }
+                todo.tags = tags;
+                self.update(&todo)?;
+                changed.push(todo);
+            }
+        }
+        Ok(changed)
+    }
+}
+
+/// Title of the list which is created if the store has no list at all.
+pub const DEFAULT_LIST_TITLE: &str = "Daily todos";
+
+/// Returns the first list of the user and creates one if there is none.
+pub fn first_list(store: &mut dyn TodoStore, user_id: u128) -> Result<TodoList, StoreError> {
+    match store.lists(Some(user_id))?.into_iter().next() {
+        Some(list) => Ok(list),
+        None => store.create_list(user_id, DEFAULT_LIST_TITLE),
+    }
+}
+
+/// Picks the backend from the `TODO_STORE` environment variable. Supported
+/// values are `memory` (default), `file` and `sqlite`. The file backend reads
+/// the path from `TODO_FILE` and falls back to `todos.json`, the SQLite backend
+/// reads it from `TODO_DATABASE` and falls back to `todos.db`.
+pub fn from_env() -> Result<Box<dyn TodoStore>, StoreError> {
+    let kind = std::env::var("TODO_STORE").unwrap_or_else(|_| "memory".to_string());
+    match kind.as_str() {
+        "file" => {
+            let path = std::env::var("TODO_FILE").unwrap_or_else(|_| "todos.json".to_string());
+            Ok(Box::new(FileStore::open(path)?))
+        }
+        "sqlite" => {
+            let path = std::env::var("TODO_DATABASE").unwrap_or_else(|_| "todos.db".to_string());
+            Ok(Box::new(SqliteStore::open(path)?))
+        }
+        "memory" => Ok(Box::new(MemoryStore::default())),
+        other => Err(StoreError::UnknownBackend(other.to_string())),
+    }
+}
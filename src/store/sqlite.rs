@@ -0,0 +1,588 @@
+use std::path::Path;
+
+use rusqlite::{
+    params,
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, Type, ValueRef},
+    Connection, OptionalExtension, Row, ToSql,
+};
+use serde::de::DeserializeOwned;
+
+use super::{StoreError, TodoStore};
+use crate::{
+    audit::{Action, AuditEntry},
+    auth::User,
+    daily::DayRecord,
+    search,
+    todo::{self, Priority, Recurrence, Todo, TodoList},
+};
+
+/// Schema migrations. The index in this list plus one is the schema version, which
+/// is kept in `PRAGMA user_version`. Never change an existing entry, always append
+/// a new one.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE todos (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL,
+        done INTEGER NOT NULL DEFAULT 0
+    );",
+    // existing todos are moved to the default list with id 1
+    "CREATE TABLE lists (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        title TEXT NOT NULL
+    );
+    INSERT INTO lists (title) VALUES ('Daily todos');
+    ALTER TABLE todos ADD COLUMN list_id INTEGER NOT NULL DEFAULT 1;
+    CREATE INDEX todos_list_id ON todos (list_id);",
+    // existing lists have no owner until the first user registers
+    "CREATE TABLE users (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+        password_hash TEXT NOT NULL
+    );
+    ALTER TABLE lists ADD COLUMN user_id INTEGER;
+    CREATE INDEX lists_user_id ON lists (user_id);",
+    // full-text index for the search, the triggers keep it in sync with the todos
+    "CREATE VIRTUAL TABLE todos_fts USING fts5(
+        name, content='todos', content_rowid='id', tokenize='trigram'
+    );
+    INSERT INTO todos_fts (todos_fts) VALUES ('rebuild');
+    CREATE TRIGGER todos_fts_insert AFTER INSERT ON todos BEGIN
+        INSERT INTO todos_fts (rowid, name) VALUES (new.id, new.name);
+    END;
+    CREATE TRIGGER todos_fts_delete AFTER DELETE ON todos BEGIN
+        INSERT INTO todos_fts (todos_fts, rowid, name) VALUES ('delete', old.id, old.name);
+    END;
+    CREATE TRIGGER todos_fts_update AFTER UPDATE OF name ON todos BEGIN
+        INSERT INTO todos_fts (todos_fts, rowid, name) VALUES ('delete', old.id, old.name);
+        INSERT INTO todos_fts (rowid, name) VALUES (new.id, new.name);
+    END;",
+    "ALTER TABLE todos ADD COLUMN due TEXT;",
+    "ALTER TABLE todos ADD COLUMN priority TEXT NOT NULL DEFAULT 'normal';",
+    // the tags with their colors as JSON array
+    "ALTER TABLE todos ADD COLUMN tags TEXT NOT NULL DEFAULT '[]';",
+    "ALTER TABLE todos ADD COLUMN parent_id INTEGER;
+    CREATE INDEX todos_parent_id ON todos (parent_id);",
+    // the manual order starts as insertion order
+    "ALTER TABLE todos ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+    UPDATE todos SET position = id;",
+    // the repeat rule in its text form, e.g. 'weekly mon,fri'
+    "ALTER TABLE todos ADD COLUMN recurrence TEXT;",
+    // the history of the daily reset, `completed` holds the names of the
+    // completed todos of a day as JSON array
+    "ALTER TABLE todos ADD COLUMN routine INTEGER NOT NULL DEFAULT 0;
+    CREATE TABLE days (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        list_id INTEGER NOT NULL,
+        date TEXT NOT NULL,
+        done INTEGER NOT NULL,
+        total INTEGER NOT NULL,
+        completed TEXT NOT NULL DEFAULT '[]'
+    );
+    CREATE INDEX days_list_id ON days (list_id);",
+    // the audit log has no foreign keys, it outlives the todos; `action` holds
+    // the action with its details as JSON
+    "CREATE TABLE audit (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        todo_id INTEGER NOT NULL,
+        list_id INTEGER NOT NULL,
+        time TEXT NOT NULL,
+        user_id INTEGER,
+        action TEXT NOT NULL
+    );
+    CREATE INDEX audit_todo_id ON audit (todo_id);",
+    "ALTER TABLE todos ADD COLUMN archived INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE todos ADD COLUMN created TEXT;
+    ALTER TABLE todos ADD COLUMN completed TEXT;",
+    "ALTER TABLE todos ADD COLUMN contexts TEXT NOT NULL DEFAULT '[]';",
+    "ALTER TABLE todos ADD COLUMN uid TEXT;",
+    "ALTER TABLE todos ADD COLUMN resource TEXT;",
+];
+
+/// The trigram index only finds queries with at least three characters.
+const MIN_INDEXED_QUERY: usize = 3;
+
+const LIST_COLUMNS: &str = "id, user_id, title";
+const TODO_COLUMNS: &str =
+    "id, list_id, parent_id, name, done, due, priority, tags, position, recurrence, routine, archived, created,
+    completed, uid, contexts, resource";
+
+/// Stores the todos in a single SQLite database file. The ids are assigned by
+/// the database.
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    /// Opens (or creates) the database at `path` and applies all pending migrations.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let mut store = SqliteStore {
+            conn: Connection::open(path)?,
+        };
+        store.migrate()?;
+        Ok(store)
+    }
+
+    fn migrate(&mut self) -> Result<(), StoreError> {
+        let version: i64 = self
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            let tx = self.conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", index as i64 + 1)?;
+            tx.commit()?;
+        }
+        Ok(())
+    }
+}
+
+fn to_user(row: &Row) -> rusqlite::Result<User> {
+    Ok(User {
+        id: row.get::<_, i64>("id")? as u128,
+        name: row.get("name")?,
+        password_hash: row.get("password_hash")?,
+    })
+}
+
+fn to_list(row: &Row) -> rusqlite::Result<TodoList> {
+    Ok(TodoList {
+        id: row.get::<_, i64>("id")? as u128,
+        user_id: row.get::<_, Option<i64>>("user_id")?.map(|id| id as u128),
+        title: row.get("title")?,
+    })
+}
+
+fn to_todo(row: &Row) -> rusqlite::Result<Todo> {
+    Ok(Todo {
+        id: row.get::<_, i64>("id")? as u128,
+        list_id: row.get::<_, i64>("list_id")? as u128,
+        parent_id: row.get::<_, Option<i64>>("parent_id")?.map(|id| id as u128),
+        name: row.get("name")?,
+        done: row.get("done")?,
+        due: row.get("due")?,
+        priority: row.get("priority")?,
+        tags: json_column(row, "tags")?,
+        position: row.get("position")?,
+        recurrence: row.get("recurrence")?,
+        routine: row.get("routine")?,
+        archived: row.get("archived")?,
+        created: row.get("created")?,
+        completed: row.get("completed")?,
+        uid: row.get("uid")?,
+        contexts: json_column(row, "contexts")?,
+        resource: row.get("resource")?,
+    })
+}
+
+/// Reads a column which contains JSON.
+fn json_column<T: DeserializeOwned>(row: &Row, name: &str) -> rusqlite::Result<T> {
+    let value: String = row.get(name)?;
+    serde_json::from_str(&value).map_err(|err| {
+        rusqlite::Error::FromSqlConversionFailure(
+            row.as_ref().column_index(name).unwrap_or_default(),
+            Type::Text,
+            Box::new(err),
+        )
+    })
+}
+
+impl ToSql for Priority {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for Priority {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let value = value.as_str()?;
+        Priority::parse(value)
+            .ok_or_else(|| FromSqlError::Other(format!("unknown priority {value}").into()))
+    }
+}
+
+impl ToSql for Recurrence {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.to_string().into())
+    }
+}
+
+impl FromSql for Recurrence {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Recurrence::try_from(value.as_str()?.to_string())
+            .map_err(|err| FromSqlError::Other(err.into()))
+    }
+}
+
+impl TodoStore for SqliteStore {
+    fn get_user(&self, id: u128) -> Result<Option<User>, StoreError> {
+        let Ok(id) = i64::try_from(id) else {
+            return Ok(None);
+        };
+        let user = self
+            .conn
+            .query_row(
+                "SELECT id, name, password_hash FROM users WHERE id = ?1",
+                [id],
+                to_user,
+            )
+            .optional()?;
+        Ok(user)
+    }
+
+    fn has_users(&self) -> Result<bool, StoreError> {
+        Ok(self
+            .conn
+            .query_row("SELECT EXISTS (SELECT 1 FROM users)", [], |row| row.get(0))?)
+    }
+
+    fn find_user(&self, name: &str) -> Result<Option<User>, StoreError> {
+        let user = self
+            .conn
+            .query_row(
+                "SELECT id, name, password_hash FROM users WHERE name = ?1",
+                [name.to_lowercase()],
+                to_user,
+            )
+            .optional()?;
+        Ok(user)
+    }
+
+    fn create_user(&mut self, name: &str, password_hash: &str) -> Result<User, StoreError> {
+        let name = name.to_lowercase();
+        self.conn.execute(
+            "INSERT INTO users (name, password_hash) VALUES (?1, ?2)",
+            [name.as_str(), password_hash],
+        )?;
+        Ok(User {
+            id: self.conn.last_insert_rowid() as u128,
+            name,
+            password_hash: password_hash.to_string(),
+        })
+    }
+
+    fn adopt_lists(&mut self, user_id: u128) -> Result<usize, StoreError> {
+        Ok(self.conn.execute(
+            "UPDATE lists SET user_id = ?1 WHERE user_id IS NULL",
+            [user_id as i64],
+        )?)
+    }
+
+    fn lists(&self, user_id: Option<u128>) -> Result<Vec<TodoList>, StoreError> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM lists WHERE ?1 IS NULL OR user_id = ?1 ORDER BY id",
+            LIST_COLUMNS
+        ))?;
+        let lists = stmt
+            .query_map([user_id.map(|id| id as i64)], to_list)?
+            .collect::<Result<_, _>>()?;
+        Ok(lists)
+    }
+
+    fn get_list(&self, id: u128) -> Result<Option<TodoList>, StoreError> {
+        let Ok(id) = i64::try_from(id) else {
+            return Ok(None);
+        };
+        let list = self
+            .conn
+            .query_row(
+                &format!("SELECT {} FROM lists WHERE id = ?1", LIST_COLUMNS),
+                [id],
+                to_list,
+            )
+            .optional()?;
+        Ok(list)
+    }
+
+    fn create_list(&mut self, user_id: u128, title: &str) -> Result<TodoList, StoreError> {
+        self.conn.execute(
+            "INSERT INTO lists (user_id, title) VALUES (?1, ?2)",
+            params![user_id as i64, title],
+        )?;
+        Ok(TodoList {
+            id: self.conn.last_insert_rowid() as u128,
+            user_id: Some(user_id),
+            title: title.to_string(),
+        })
+    }
+
+    fn update_list(&mut self, list: &TodoList) -> Result<bool, StoreError> {
+        let Ok(id) = i64::try_from(list.id) else {
+            return Ok(false);
+        };
+        let changed = self.conn.execute(
+            "UPDATE lists SET user_id = ?1, title = ?2 WHERE id = ?3",
+            params![list.user_id.map(|id| id as i64), list.title, id],
+        )?;
+        Ok(changed > 0)
+    }
+
+    fn remove_list(&mut self, id: u128) -> Result<bool, StoreError> {
+        let Ok(id) = i64::try_from(id) else {
+            return Ok(false);
+        };
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM todos WHERE list_id = ?1", [id])?;
+        tx.execute("DELETE FROM days WHERE list_id = ?1", [id])?;
+        let changed = tx.execute("DELETE FROM lists WHERE id = ?1", [id])?;
+        tx.commit()?;
+        Ok(changed > 0)
+    }
+
+    fn list(&self, list_id: Option<u128>) -> Result<Vec<Todo>, StoreError> {
+        let todos = match list_id {
+            Some(list_id) => {
+                let Ok(list_id) = i64::try_from(list_id) else {
+                    return Ok(vec![]);
+                };
+                let mut stmt = self.conn.prepare(&format!(
+                    "SELECT {} FROM todos WHERE list_id = ?1 AND archived = 0
+                        ORDER BY position, id",
+                    TODO_COLUMNS
+                ))?;
+                let todos = stmt
+                    .query_map([list_id], to_todo)?
+                    .collect::<Result<_, _>>()?;
+                todos
+            }
+            None => {
+                let mut stmt = self.conn.prepare(&format!(
+                    "SELECT {} FROM todos WHERE archived = 0 ORDER BY list_id, position, id",
+                    TODO_COLUMNS
+                ))?;
+                let todos = stmt.query_map([], to_todo)?.collect::<Result<_, _>>()?;
+                todos
+            }
+        };
+        Ok(todos)
+    }
+
+    fn archived(&self, list_id: u128) -> Result<Vec<Todo>, StoreError> {
+        let Ok(list_id) = i64::try_from(list_id) else {
+            return Ok(vec![]);
+        };
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM todos WHERE list_id = ?1 AND archived = 1 ORDER BY position, id",
+            TODO_COLUMNS
+        ))?;
+        let todos = stmt
+            .query_map([list_id], to_todo)?
+            .collect::<Result<_, _>>()?;
+        Ok(todos)
+    }
+
+    fn get(&self, id: u128) -> Result<Option<Todo>, StoreError> {
+        let Ok(id) = i64::try_from(id) else {
+            return Ok(None);
+        };
+        let todo = self
+            .conn
+            .query_row(
+                &format!("SELECT {} FROM todos WHERE id = ?1", TODO_COLUMNS),
+                [id],
+                to_todo,
+            )
+            .optional()?;
+        Ok(todo)
+    }
+
+    fn create(&mut self, mut todo: Todo) -> Result<Todo, StoreError> {
+        todo.created = todo.created.or_else(|| Some(todo::today()));
+        self.conn.execute(
+            "INSERT INTO todos (list_id, name, done, due, priority, tags, parent_id, recurrence, routine,
+                    archived, created, completed, uid, contexts, resource, position)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15,
+                    (SELECT COALESCE(MAX(position), 0) + 1 FROM todos WHERE list_id = ?1))",
+            params![
+                todo.list_id as i64,
+                todo.name,
+                todo.done,
+                todo.due,
+                todo.priority,
+                serde_json::to_string(&todo.tags)?,
+                todo.parent_id.map(|id| id as i64),
+                todo.recurrence,
+                todo.routine,
+                todo.archived,
+                todo.created,
+                todo.completed,
+                todo.uid,
+                serde_json::to_string(&todo.contexts)?,
+                todo.resource
+            ],
+        )?;
+        todo.id = self.conn.last_insert_rowid() as u128;
+        todo.position = self.conn.query_row(
+            "SELECT position FROM todos WHERE id = ?1",
+            [todo.id as i64],
+            |row| row.get(0),
+        )?;
+        Ok(todo)
+    }
+
+    fn update(&mut self, todo: &Todo) -> Result<bool, StoreError> {
+        let Ok(id) = i64::try_from(todo.id) else {
+            return Ok(false);
+        };
+        let changed = self.conn.execute(
+            "UPDATE todos SET list_id = ?1, name = ?2, done = ?3, due = ?4, priority = ?5, tags = ?6,
+                parent_id = ?7, position = ?8, recurrence = ?9, routine = ?10, archived = ?11,
+                created = ?12, completed = ?13, uid = ?14, contexts = ?15, resource = ?16
+                WHERE id = ?17",
+            params![
+                todo.list_id as i64,
+                todo.name,
+                todo.done,
+                todo.due,
+                todo.priority,
+                serde_json::to_string(&todo.tags)?,
+                todo.parent_id.map(|id| id as i64),
+                todo.position,
+                todo.recurrence,
+                todo.routine,
+                todo.archived,
+                todo.created,
+                todo.completed,
+                todo.uid,
+                serde_json::to_string(&todo.contexts)?,
+                todo.resource,
+                id
+            ],
+        )?;
+        Ok(changed > 0)
+    }
+
+    fn restore(&mut self, todo: &Todo) -> Result<(), StoreError> {
+        self.conn.execute(
+            &format!(
+                "INSERT INTO todos ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+                TODO_COLUMNS
+            ),
+            params![
+                todo.id as i64,
+                todo.list_id as i64,
+                todo.parent_id.map(|id| id as i64),
+                todo.name,
+                todo.done,
+                todo.due,
+                todo.priority,
+                serde_json::to_string(&todo.tags)?,
+                todo.position,
+                todo.recurrence,
+                todo.routine,
+                todo.archived,
+                todo.created,
+                todo.completed,
+                todo.uid,
+                serde_json::to_string(&todo.contexts)?,
+                todo.resource
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn remove(&mut self, id: u128) -> Result<bool, StoreError> {
+        let Ok(id) = i64::try_from(id) else {
+            return Ok(false);
+        };
+        let changed = self
+            .conn
+            .execute("DELETE FROM todos WHERE id = ?1 OR parent_id = ?1", [id])?;
+        Ok(changed > 0)
+    }
+
+    fn add_day(&mut self, record: &DayRecord) -> Result<(), StoreError> {
+        self.conn.execute(
+            "INSERT INTO days (list_id, date, done, total, completed) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                record.list_id as i64,
+                record.date,
+                record.done as i64,
+                record.total as i64,
+                serde_json::to_string(&record.completed)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn days(&self, list_id: u128) -> Result<Vec<DayRecord>, StoreError> {
+        let Ok(list_id) = i64::try_from(list_id) else {
+            return Ok(vec![]);
+        };
+        let mut stmt = self.conn.prepare(
+            "SELECT list_id, date, done, total, completed FROM days
+                WHERE list_id = ?1 ORDER BY id DESC",
+        )?;
+        let days = stmt
+            .query_map([list_id], |row| {
+                Ok(DayRecord {
+                    list_id: row.get::<_, i64>("list_id")? as u128,
+                    date: row.get("date")?,
+                    done: row.get::<_, i64>("done")? as usize,
+                    total: row.get::<_, i64>("total")? as usize,
+                    completed: json_column(row, "completed")?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(days)
+    }
+
+    fn append_audit(&mut self, entry: &AuditEntry) -> Result<(), StoreError> {
+        self.conn.execute(
+            "INSERT INTO audit (todo_id, list_id, time, user_id, action) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                entry.todo_id as i64,
+                entry.list_id as i64,
+                entry.time,
+                entry.user_id.map(|id| id as i64),
+                serde_json::to_string(&entry.action)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn audit(&self, todo_id: u128) -> Result<Vec<AuditEntry>, StoreError> {
+        let Ok(todo_id) = i64::try_from(todo_id) else {
+            return Ok(vec![]);
+        };
+        let mut stmt = self.conn.prepare(
+            "SELECT todo_id, list_id, time, user_id, action FROM audit
+                WHERE todo_id = ?1 ORDER BY id",
+        )?;
+        let entries = stmt
+            .query_map([todo_id], |row| {
+                Ok(AuditEntry {
+                    todo_id: row.get::<_, i64>("todo_id")? as u128,
+                    list_id: row.get::<_, i64>("list_id")? as u128,
+                    time: row.get("time")?,
+                    user_id: row.get::<_, Option<i64>>("user_id")?.map(|id| id as u128),
+                    action: json_column::<Action>(row, "action")?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(entries)
+    }
+
+    fn search(&self, list_id: u128, query: &str) -> Result<Vec<Todo>, StoreError> {
+        if query.chars().count() < MIN_INDEXED_QUERY {
+            let mut todos = self.list(Some(list_id))?;
+            todos.retain(|todo| search::matches(&todo.name, query));
+            return Ok(todos);
+        }
+        // a quoted phrase finds the query as substring, quotes inside are doubled
+        let phrase = format!("\"{}\"", query.replace('"', "\"\""));
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM todos WHERE list_id = ?1 AND archived = 0
+                AND id IN (SELECT rowid FROM todos_fts WHERE todos_fts MATCH ?2)
+                ORDER BY position, id",
+            TODO_COLUMNS
+        ))?;
+        let todos = stmt
+            .query_map(params![list_id as i64, phrase], to_todo)?
+            .collect::<Result<Vec<_>, _>>()?;
+        // the index folds the case slightly differently, so the matches are checked again
+        Ok(todos
+            .into_iter()
+            .filter(|todo| search::matches(&todo.name, query))
+            .collect())
+    }
+}
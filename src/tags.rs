@@ -0,0 +1,199 @@
+//! The tag page to rename, recolor and merge the tags of all todos of a user.
+
+use std::sync::Mutex;
+
+use actix_web::{get, post, web, HttpResponse, Responder};
+use maud::{html, Markup, DOCTYPE};
+use serde::Deserialize;
+
+use crate::{
+    audit::{self, Action},
+    auth::CurrentUser,
+    events::TodoEvent,
+    store::{StoreError, TodoStore},
+    todo::{self, Tag, TagColor},
+    ApiError, AppState,
+};
+
+/// Turns tag names into tags. Tags which the user already has keep their
+/// color, new tags get a color derived from the name.
+pub fn resolve(
+    store: &dyn TodoStore,
+    user_id: u128,
+    names: Vec<String>,
+) -> Result<Vec<Tag>, StoreError> {
+    if names.is_empty() {
+        return Ok(vec![]);
+    }
+    let known = store.tags(user_id)?;
+    Ok(names
+        .into_iter()
+        .map(
+            |name| match known.iter().find(|(tag, _)| tag.name == name) {
+                Some((tag, _)) => tag.clone(),
+                None => Tag {
+                    color: TagColor::for_name(&name),
+                    name,
+                },
+            },
+        )
+        .collect())
+}
+
+fn render_tags(tags: &[(Tag, usize)], error: Option<&str>) -> Markup {
+    html! {
+        div #tags class="flex flex-col gap-4" {
+            @if let Some(error) = error {
+                div class="text-sm text-red-500" { (error) }
+            }
+            @if tags.is_empty() {
+                p class="text-neutral-400" {
+                    "There are no tags yet. Add them with #hashtags when adding a todo or in the edit form of a todo."
+                }
+            }
+            @for (tag, count) in tags {
+                div class="flex flex-row gap-4 items-center" {
+                    form class="flex-1 flex flex-row gap-2 items-center" hx-post="/tags" hx-target="#tags" hx-swap="outerHTML" {
+                        input type="hidden" name="tag" value=(tag.name) ;
+                        span class={ "rounded-full px-2 text-sm " (tag.color.class()) } { "#" (tag.name) }
+                        input name="name" value=(tag.name) class="flex-1 border rounded border-neutral-400 text-sm px-2 py-1 bg-black" ;
+                        select name="color" class="border rounded border-neutral-400 text-sm px-2 py-1 bg-black" {
+                            @for color in TagColor::ALL {
+                                option value=(color.as_str()) selected[color == tag.color] { (color.as_str()) }
+                            }
+                        }
+                        button class="rounded bg-blue-500 px-2 py-1 text-sm" {"Save"}
+                    }
+                    span class="w-16 text-sm text-neutral-400" {
+                        (count) @if *count == 1 { " todo" } @else { " todos" }
+                    }
+                    @if tags.len() > 1 {
+                        form class="flex flex-row gap-2 items-center" hx-post="/tags/merge" hx-target="#tags" hx-swap="outerHTML" {
+                            input type="hidden" name="tag" value=(tag.name) ;
+                            select name="into" class="border rounded border-neutral-400 text-sm px-2 py-1 bg-black" {
+                                @for (other, _) in tags.iter().filter(|(other, _)| other.name != tag.name) {
+                                    option value=(other.name) { "#" (other.name) }
+                                }
+                            }
+                            button class="rounded border border-neutral-400 px-2 py-1 text-sm" {"Merge"}
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[get("/tags")]
+async fn page(
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+) -> Result<HttpResponse, ApiError> {
+    let state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let tags = state.store.tags(user.0)?;
+    let body = html! {
+        (DOCTYPE)
+        script src="/assets/tailwind.min.js" {}
+        script src="/assets/htmx.min.js"{}
+        link rel="icon" type="image/png" href="/assets/favicon.png";
+        link src="/assets/global.css" rel="stylesheet" {}
+        title { "Tags - Todo" }
+
+        body ."min-h-sreen" .text-white .bg-black ."p-4" {
+            main class="container m-auto max-w-2xl flex flex-col gap-4" {
+                a href="/" class="text-sm text-neutral-400" { "← Lists" }
+                h1 class="text-2xl" { "Tags" }
+                p class="text-sm text-neutral-400" {
+                    "Changes apply to all todos with the tag. Renaming a tag to the name of another tag merges both."
+                }
+                (render_tags(&tags, None))
+            }
+        }
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body.into_string()))
+}
+
+/// Replaces the tag on all todos of the user and publishes the changed todos.
+fn replace_tag(
+    state: &mut AppState,
+    user: CurrentUser,
+    name: &str,
+    tag: &Tag,
+) -> Result<(), ApiError> {
+    for todo in state.store.update_tag(user.0, name, tag)? {
+        let fields = vec!["tags".to_string()];
+        audit::record(state, Some(user.0), &todo, Action::Changed { fields })?;
+        state.events.send(TodoEvent::Changed(todo));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct TagForm {
+    tag: String,
+    name: String,
+    color: TagColor,
+}
+
+#[post("/tags")]
+async fn update(
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+    form: web::Form<TagForm>,
+) -> impl Responder {
+    let mut state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let error = match todo::validate_tag(&form.name) {
+        Ok(name) => {
+            let tag = Tag {
+                name,
+                color: form.color,
+            };
+            replace_tag(&mut state, user, &form.tag, &tag)?;
+            None
+        }
+        Err(error) => Some(error),
+    };
+    let tags = state.store.tags(user.0)?;
+    Ok(HttpResponse::Ok().body(render_tags(&tags, error).into_string()))
+}
+
+#[derive(Deserialize)]
+struct MergeForm {
+    tag: String,
+    into: String,
+}
+
+/// Merges the tag into another one, which keeps its color.
+#[post("/tags/merge")]
+async fn merge(
+    user: CurrentUser,
+    data: web::Data<Mutex<AppState>>,
+    form: web::Form<MergeForm>,
+) -> impl Responder {
+    let mut state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let target = state
+        .store
+        .tags(user.0)?
+        .into_iter()
+        .find(|(tag, _)| tag.name == form.into);
+    let error = match target {
+        Some((target, _)) => {
+            replace_tag(&mut state, user, &form.tag, &target)?;
+            None
+        }
+        None => Some("The tag does not exist"),
+    };
+    let tags = state.store.tags(user.0)?;
+    Ok(HttpResponse::Ok().body(render_tags(&tags, error).into_string()))
+}
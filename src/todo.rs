@@ -0,0 +1,897 @@
+use std::{cmp::Reverse, fmt};
+
+use chrono::{Datelike, Days, Local, Months, NaiveDate, Weekday};
+use maud::{html, Markup};
+use serde::{Deserialize, Serialize};
+
+use crate::search;
+
+/// Maximum number of characters of a todo name.
+pub const MAX_NAME_LENGTH: usize = 200;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Todo {
+    pub id: u128,
+    /// Todos stored before there were multiple lists belong to the first list.
+    #[serde(default)]
+    pub list_id: u128,
+    /// The todo this todo is a subtask of. Subtasks cannot have subtasks.
+    #[serde(default)]
+    pub parent_id: Option<u128>,
+    pub name: String,
+    pub done: bool,
+    #[serde(default)]
+    pub due: Option<NaiveDate>,
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(default)]
+    pub tags: Vec<Tag>,
+    /// The `@context`s of todo.txt, like `phone`. They are shown with the todo
+    /// and written back to todo.txt, the web frontend does not change them.
+    #[serde(default)]
+    pub contexts: Vec<String>,
+    /// Position in the manual order of the list. Todos with the same position
+    /// keep their insertion order.
+    #[serde(default)]
+    pub position: i64,
+    /// Completing a recurring todo adds its next occurrence to the list.
+    #[serde(default)]
+    pub recurrence: Option<Recurrence>,
+    /// Daily routines are unchecked by the daily reset instead of being
+    /// archived with the other completed todos.
+    #[serde(default)]
+    pub routine: bool,
+    /// Archived todos are kept out of the list, the statistic and the search
+    /// until they are restored on the archive page.
+    #[serde(default)]
+    pub archived: bool,
+    /// The day the todo was added, set by the store.
+    #[serde(default)]
+    pub created: Option<NaiveDate>,
+    /// The day the todo was completed, kept up to date by `set_done`.
+    #[serde(default)]
+    pub completed: Option<NaiveDate>,
+    /// The iCalendar `UID` given by a CalDAV client. Other todos are known by
+    /// a `UID` made from their id.
+    #[serde(default)]
+    pub uid: Option<String>,
+    /// The name of the CalDAV resource chosen by the client, like `a1b2.ics`,
+    /// as it appears in the path. Other todos are named after their `UID`.
+    #[serde(default)]
+    pub resource: Option<String>,
+}
+
+/// A tag of a todo. The color is stored with every todo which has the tag, the
+/// tag page changes it on all todos of the user at once.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub name: String,
+    pub color: TagColor,
+}
+
+impl Tag {
+    /// Chip which filters the list of the todo by the tag.
+    fn render_chip(&self, list_id: u128) -> Markup {
+        html! {
+            button class={ "rounded-full px-2 text-sm " (self.color.class()) }
+            hx-get=(format!("/lists/{}/items?tag={}", list_id, form_urlencoded::byte_serialize(self.name.as_bytes()).collect::<String>()))
+            hx-include="#filter, #sort, #search"
+            hx-target="#todo-list" {
+                "#" (self.name)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TagColor {
+    Gray,
+    Red,
+    Orange,
+    Yellow,
+    Green,
+    Teal,
+    Blue,
+    Purple,
+    Pink,
+}
+
+impl TagColor {
+    pub const ALL: [TagColor; 9] = [
+        TagColor::Gray,
+        TagColor::Red,
+        TagColor::Orange,
+        TagColor::Yellow,
+        TagColor::Green,
+        TagColor::Teal,
+        TagColor::Blue,
+        TagColor::Purple,
+        TagColor::Pink,
+    ];
+
+    /// Color of a new tag, so that different tags likely get different colors.
+    pub fn for_name(name: &str) -> TagColor {
+        let hash = name.bytes().map(usize::from).sum::<usize>();
+        TagColor::ALL[hash % TagColor::ALL.len()]
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TagColor::Gray => "gray",
+            TagColor::Red => "red",
+            TagColor::Orange => "orange",
+            TagColor::Yellow => "yellow",
+            TagColor::Green => "green",
+            TagColor::Teal => "teal",
+            TagColor::Blue => "blue",
+            TagColor::Purple => "purple",
+            TagColor::Pink => "pink",
+        }
+    }
+
+    /// Tailwind classes of the chips.
+    pub fn class(self) -> &'static str {
+        match self {
+            TagColor::Gray => "bg-neutral-700",
+            TagColor::Red => "bg-red-800",
+            TagColor::Orange => "bg-orange-800",
+            TagColor::Yellow => "bg-yellow-700",
+            TagColor::Green => "bg-green-800",
+            TagColor::Teal => "bg-teal-800",
+            TagColor::Blue => "bg-blue-800",
+            TagColor::Purple => "bg-purple-800",
+            TagColor::Pink => "bg-pink-800",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TodoList {
+    pub id: u128,
+    /// Owner of the list. Lists created before there were user accounts have
+    /// no owner until the first user registers.
+    #[serde(default)]
+    pub user_id: Option<u128>,
+    pub title: String,
+}
+
+impl TodoList {
+    pub fn url(&self) -> String {
+        format!("/lists/{}", self.id)
+    }
+}
+
+/// Which todos of a list are shown, selected with `?filter=all|active|done`.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Filter {
+    #[default]
+    All,
+    Active,
+    Done,
+}
+
+impl Filter {
+    pub const ALL: [Filter; 3] = [Filter::All, Filter::Active, Filter::Done];
+
+    pub fn matches(self, todo: &Todo) -> bool {
+        match self {
+            Filter::All => true,
+            Filter::Active => !todo.done,
+            Filter::Done => todo.done,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Filter::All => "all",
+            Filter::Active => "active",
+            Filter::Done => "done",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Filter::All => "All",
+            Filter::Active => "Active",
+            Filter::Done => "Completed",
+        }
+    }
+}
+
+/// Importance of a todo. The variants are declared from the lowest to the
+/// highest priority, so they can be compared.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Urgent,
+}
+
+impl Priority {
+    /// All priorities from the highest to the lowest, the order of the select
+    /// inputs and the statistic.
+    pub const ALL: [Priority; 4] = [
+        Priority::Urgent,
+        Priority::High,
+        Priority::Normal,
+        Priority::Low,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+            Priority::Urgent => "urgent",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Priority> {
+        Priority::ALL
+            .into_iter()
+            .find(|priority| priority.as_str() == value)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Priority::Low => "Low",
+            Priority::Normal => "Normal",
+            Priority::High => "High",
+            Priority::Urgent => "Urgent",
+        }
+    }
+
+    /// Tailwind classes of the badge.
+    pub fn color(self) -> &'static str {
+        match self {
+            Priority::Low => "border border-neutral-600 text-neutral-400",
+            Priority::Normal => "border border-neutral-400",
+            Priority::High => "bg-orange-600",
+            Priority::Urgent => "bg-red-600",
+        }
+    }
+
+    /// A select input with the current priority selected.
+    pub fn render_select(current: Priority) -> Markup {
+        html! {
+            select name="priority" title="Priority" class="border rounded border-neutral-400 text-sm px-2 py-2 bg-black" {
+                @for priority in Priority::ALL {
+                    option value=(priority.as_str()) selected[priority == current] {
+                        (priority.label())
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// When a recurring todo comes back after it was completed. The forms, the
+/// API and the storage use the text form, e.g. `weekly mon,fri`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Recurrence {
+    Daily,
+    /// Monday to Friday.
+    Weekdays,
+    /// On the given days of the week, sorted from Monday.
+    Weekly(Vec<Weekday>),
+    /// On the given day of the month, or on the last day of shorter months.
+    Monthly(u32),
+    /// Every given number of days.
+    Days(u32),
+}
+
+impl Recurrence {
+    /// Examples for the rules, offered by the repeat inputs.
+    pub const EXAMPLES: [&'static str; 5] = [
+        "daily",
+        "weekdays",
+        "weekly mon,fri",
+        "monthly 1",
+        "every 2 days",
+    ];
+
+    /// Parses a rule like `daily`, `weekdays`, `weekly mon,fri`, `monthly 15`
+    /// or `every 3 days`. An empty value means the todo does not recur.
+    pub fn parse(value: &str) -> Result<Option<Recurrence>, &'static str> {
+        const INVALID: &str =
+            "The repeat rule is invalid, use daily, weekdays, weekly mon,fri, monthly 15 or every 3 days";
+        let value = value.trim().to_lowercase();
+        let words: Vec<&str> = value
+            .split([' ', ','])
+            .filter(|word| !word.is_empty())
+            .collect();
+        let recurrence = match words.as_slice() {
+            [] => return Ok(None),
+            ["daily"] => Recurrence::Daily,
+            ["weekdays"] => Recurrence::Weekdays,
+            ["weekly", days @ ..] if !days.is_empty() => {
+                let mut days = days
+                    .iter()
+                    .map(|day| day.parse::<Weekday>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| INVALID)?;
+                days.sort_by_key(Weekday::num_days_from_monday);
+                days.dedup();
+                Recurrence::Weekly(days)
+            }
+            ["monthly", day] => match day.parse() {
+                Ok(day @ 1..=31) => Recurrence::Monthly(day),
+                _ => return Err("The day of the month must be between 1 and 31"),
+            },
+            ["every", days] | ["every", days, "day" | "days"] => match days.parse() {
+                Ok(days @ 1..=366) => Recurrence::Days(days),
+                _ => return Err("The number of days must be between 1 and 366"),
+            },
+            _ => return Err(INVALID),
+        };
+        Ok(Some(recurrence))
+    }
+
+    /// The first date of the rule after the given date.
+    pub fn next(&self, after: NaiveDate) -> Option<NaiveDate> {
+        let next_day = |matches: &dyn Fn(Weekday) -> bool| {
+            (1..=7)
+                .filter_map(|days| after.checked_add_days(Days::new(days)))
+                .find(|date| matches(date.weekday()))
+        };
+        match self {
+            Recurrence::Daily => after.checked_add_days(Days::new(1)),
+            Recurrence::Weekdays => {
+                next_day(&|weekday| !matches!(weekday, Weekday::Sat | Weekday::Sun))
+            }
+            Recurrence::Weekly(days) => next_day(&|weekday| days.contains(&weekday)),
+            Recurrence::Monthly(day) => (0..2).find_map(|months| {
+                let month = after.with_day(1)?.checked_add_months(Months::new(months))?;
+                let last = month.checked_add_months(Months::new(1))?.pred_opt()?.day();
+                let date = month.with_day((*day).min(last))?;
+                (date > after).then_some(date)
+            }),
+            Recurrence::Days(days) => after.checked_add_days(Days::new(u64::from(*days))),
+        }
+    }
+
+    /// A text input for the rule, which suggests the examples of the
+    /// `#repeat-rules` list on the page.
+    pub fn render_input(value: &str) -> Markup {
+        html! {
+            input name="repeat" value=(value) list="repeat-rules" placeholder="Repeat" title="Repeat, e.g. daily, weekdays, weekly mon,fri, monthly 15 or every 3 days" class="w-36 border rounded border-neutral-400 text-sm px-4 py-2 bg-black" ;
+        }
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Recurrence::Daily => write!(f, "daily"),
+            Recurrence::Weekdays => write!(f, "weekdays"),
+            Recurrence::Weekly(days) => {
+                let days: Vec<String> = days
+                    .iter()
+                    .map(|day| day.to_string().to_lowercase())
+                    .collect();
+                write!(f, "weekly {}", days.join(","))
+            }
+            Recurrence::Monthly(day) => write!(f, "monthly {}", day),
+            Recurrence::Days(1) => write!(f, "every 1 day"),
+            Recurrence::Days(days) => write!(f, "every {} days", days),
+        }
+    }
+}
+
+impl TryFrom<String> for Recurrence {
+    type Error = &'static str;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Recurrence::parse(&value)?.ok_or("The repeat rule must not be empty")
+    }
+}
+
+impl From<Recurrence> for String {
+    fn from(recurrence: Recurrence) -> Self {
+        recurrence.to_string()
+    }
+}
+
+/// Order of the todos of a list, selected with `?sort=manual|due|priority`.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Sort {
+    /// The order set by dragging the todos, new todos are added at the end.
+    #[default]
+    #[serde(alias = "added")]
+    Manual,
+    Due,
+    Priority,
+}
+
+impl Sort {
+    pub const ALL: [Sort; 3] = [Sort::Manual, Sort::Due, Sort::Priority];
+
+    /// Sorts the todos, which are expected in manual order. Todos without
+    /// due date come last, the highest priority comes first.
+    pub fn apply(self, todos: &mut [Todo]) {
+        match self {
+            Sort::Manual => {}
+            Sort::Due => todos.sort_by_key(|todo| (todo.due.is_none(), todo.due)),
+            Sort::Priority => todos.sort_by_key(|todo| Reverse(todo.priority)),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Sort::Manual => "manual",
+            Sort::Due => "due",
+            Sort::Priority => "priority",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Sort::Manual => "Manual",
+            Sort::Due => "Due date",
+            Sort::Priority => "Priority",
+        }
+    }
+}
+
+/// The current date in the local time zone of the server.
+pub fn today() -> NaiveDate {
+    Local::now().date_naive()
+}
+
+/// Parses the value of a date input. An empty value means no due date.
+pub fn parse_due(value: &str) -> Result<Option<NaiveDate>, &'static str> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(date) => Ok(Some(date)),
+        Err(_) => Err("The due date is invalid"),
+    }
+}
+
+/// Describes the due date relative to today, e.g. "tomorrow" or "3 days overdue".
+pub fn due_label(due: NaiveDate, today: NaiveDate) -> String {
+    match (due - today).num_days() {
+        0 => "today".to_string(),
+        1 => "tomorrow".to_string(),
+        -1 => "1 day overdue".to_string(),
+        days if days < 0 => format!("{} days overdue", -days),
+        days => format!("in {} days", days),
+    }
+}
+
+/// The values of the edit form of a todo as entered by the user.
+#[derive(Deserialize)]
+pub struct EditForm {
+    pub name: String,
+    #[serde(default)]
+    pub due: String,
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(default)]
+    pub tags: String,
+    #[serde(default)]
+    pub repeat: String,
+    #[serde(default)]
+    pub routine: bool,
+}
+
+impl EditForm {
+    /// The form filled with the stored values of the todo.
+    pub fn new(todo: &Todo) -> Self {
+        EditForm {
+            name: todo.name.clone(),
+            due: todo.due.map(|due| due.to_string()).unwrap_or_default(),
+            priority: todo.priority,
+            tags: todo
+                .tags
+                .iter()
+                .map(|tag| tag.name.as_str())
+                .collect::<Vec<_>>()
+                .join(" "),
+            repeat: todo
+                .recurrence
+                .as_ref()
+                .map(Recurrence::to_string)
+                .unwrap_or_default(),
+            routine: todo.routine,
+        }
+    }
+}
+
+/// The subtasks of the todo in manual order.
+pub fn subtasks(todos: &[Todo], parent_id: u128) -> Vec<Todo> {
+    todos
+        .iter()
+        .filter(|todo| todo.parent_id == Some(parent_id))
+        .cloned()
+        .collect()
+}
+
+/// Renders the todo with its subtasks, or its parent if it is a subtask. The
+/// parent shows the progress of its subtasks, so it is rendered again when a
+/// subtask is toggled or deleted. Returns `None` if the todo does not exist
+/// anymore.
+pub fn render_top(todos: &[Todo], todo: &Todo, oob: Option<&str>) -> Option<Markup> {
+    let id = todo.parent_id.unwrap_or(todo.id);
+    let top = todos.iter().find(|todo| todo.id == id)?;
+    Some(top.render_item(&subtasks(todos, id), oob, ""))
+}
+
+/// Maximum number of characters of a tag name.
+pub const MAX_TAG_LENGTH: usize = 32;
+
+/// Normalizes a tag name: without the leading `#` and in lowercase. Tags may
+/// contain letters, digits, `-` and `_`.
+pub fn validate_tag(name: &str) -> Result<String, &'static str> {
+    let name = name.trim();
+    let name = name.strip_prefix('#').unwrap_or(name).to_lowercase();
+    if name.is_empty() {
+        return Err("The tag must not be empty");
+    }
+    if name.chars().count() > MAX_TAG_LENGTH {
+        return Err("The tag is too long");
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err("Tags may only contain letters, digits, - and _");
+    }
+    Ok(name)
+}
+
+/// Validates all tags and drops duplicates.
+pub fn validate_tags<'a>(
+    names: impl IntoIterator<Item = &'a str>,
+) -> Result<Vec<String>, &'static str> {
+    let mut tags: Vec<String> = vec![];
+    for name in names {
+        let tag = validate_tag(name)?;
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    Ok(tags)
+}
+
+/// Parses the tags input of the edit form, the tags are separated by spaces or
+/// commas.
+pub fn parse_tags(value: &str) -> Result<Vec<String>, &'static str> {
+    validate_tags(
+        value
+            .split([' ', ','])
+            .filter(|word| !word.trim().is_empty()),
+    )
+}
+
+/// Splits the `#hashtags` off the prompt of the add form. Words which are no
+/// valid tag, like a single `#`, stay in the name.
+pub fn parse_prompt(prompt: &str) -> (String, Vec<String>) {
+    let mut words = vec![];
+    let mut tags: Vec<String> = vec![];
+    for word in prompt.split_whitespace() {
+        match word.strip_prefix('#').map(validate_tag) {
+            Some(Ok(tag)) => {
+                if !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+            _ => words.push(word),
+        }
+    }
+    if tags.is_empty() {
+        return (prompt.to_string(), tags);
+    }
+    (words.join(" "), tags)
+}
+
+/// Trims the name and checks that it is neither empty nor too long and has no
+/// control characters like line breaks.
+pub fn validate_name(name: &str) -> Result<String, &'static str> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("The name must not be empty");
+    }
+    if name.chars().count() > MAX_NAME_LENGTH {
+        return Err("The name is too long");
+    }
+    if name.chars().any(char::is_control) {
+        return Err("The name must not contain control characters");
+    }
+    Ok(name.to_string())
+}
+
+impl Todo {
+    pub fn url(&self) -> String {
+        format!("/lists/{}/{}", self.list_id, self.id)
+    }
+
+    pub fn has_tag(&self, name: &str) -> bool {
+        self.tags.iter().any(|tag| tag.name == name)
+    }
+
+    /// The next occurrence of a recurring todo. It is due on the next date of
+    /// the rule after the due date, or after today if the todo is overdue or
+    /// has no due date.
+    pub fn next_occurrence(&self, today: NaiveDate) -> Option<Todo> {
+        let recurrence = self.recurrence.as_ref()?;
+        let after = self.due.map_or(today, |due| due.max(today));
+        Some(Todo {
+            id: 0,
+            done: false,
+            due: Some(recurrence.next(after)?),
+            position: 0,
+            created: None,
+            completed: None,
+            uid: None,
+            resource: None,
+            ..self.clone()
+        })
+    }
+
+    /// Checks or unchecks the todo. The completion date is set to today or
+    /// removed if the state changes.
+    pub fn set_done(&mut self, done: bool) {
+        if done != self.done {
+            self.completed = done.then(today);
+        }
+        self.done = done;
+    }
+
+    /// Open todos whose due date has passed.
+    pub fn is_overdue(&self, today: NaiveDate) -> bool {
+        !self.done && self.due.is_some_and(|due| due < today)
+    }
+
+    /// Renders the item with its subtasks.
+    pub fn render(&self, subtasks: &[Todo]) -> Markup {
+        self.render_item(subtasks, None, "")
+    }
+
+    /// Renders the item with the matches of the search query highlighted.
+    pub fn render_match(&self, subtasks: &[Todo], query: &str) -> Markup {
+        self.render_item(subtasks, None, query)
+    }
+
+    /// Renders the item as out-of-band swap, e.g. `true` to replace or
+    /// `delete` to remove the item on the page.
+    pub fn render_oob(&self, subtasks: &[Todo], swap: &str) -> Markup {
+        self.render_item(subtasks, Some(swap), "")
+    }
+
+    fn render_item(&self, subtasks: &[Todo], oob: Option<&str>, query: &str) -> Markup {
+        let id = format!("todo-{}", self.id);
+        // toggling or deleting a subtask changes the progress of the parent
+        let target = format!("#todo-{}", self.parent_id.unwrap_or(self.id));
+        let open = subtasks.iter().filter(|todo| !todo.done).count();
+        let today = today();
+        html!(
+            li id=(id) data-id=(self.id) class="flex flex-col gap-1" hx-swap-oob=[oob] {
+                div class="flex flex-row gap-4" {
+                    span data-drag-handle draggable="true" class="cursor-move text-neutral-500" title="Drag to reorder" { "⠿" }
+                    div ."flex-1" .line-through[self.done] hx-get=(format!("{}/edit", self.url())) hx-trigger="dblclick" hx-target=(format!("#{}", id)) hx-swap="outerHTML" {
+                        (search::highlight(&self.name, query))
+                    }
+                    @for tag in &self.tags {
+                        (tag.render_chip(self.list_id))
+                    }
+                    @for context in &self.contexts {
+                        span class="text-sm text-neutral-400" title="Context" { "@" (context) }
+                    }
+                    @if self.priority != Priority::Normal {
+                        span class={ "rounded px-2 text-sm " (self.priority.color()) } {
+                            (self.priority.label())
+                        }
+                    }
+                    @if let Some(due) = self.due {
+                        span .text-sm
+                        ."text-red-500"[self.is_overdue(today)]
+                        ."text-yellow-400"[!self.done && due == today]
+                        ."text-neutral-400"[self.done || due > today]
+                        title=(due.format("%Y-%m-%d")) {
+                            (due_label(due, today))
+                        }
+                    }
+                    @if let Some(recurrence) = &self.recurrence {
+                        span class="text-sm text-neutral-400" title="Repeats" { "↻ " (recurrence.to_string()) }
+                    }
+                    @if self.routine {
+                        span class="text-sm text-neutral-400" title="Unchecked by the daily reset" { "routine" }
+                    }
+                    @if !subtasks.is_empty() {
+                        span class="text-sm text-neutral-400" title="Completed subtasks" {
+                            (subtasks.len() - open) "/" (subtasks.len())
+                        }
+                    }
+                    @if self.parent_id.is_none() {
+                        button class="text-sm text-neutral-400" hx-get=(format!("{}/subtask", self.url())) hx-target=(format!("#subtask-form-{}", self.id)) hx-swap="outerHTML" {"Subtask"}
+                    }
+                    button class="text-sm text-neutral-400" hx-get=(format!("{}/history", self.url())) hx-target=(format!("#history-{}", self.id)) hx-swap="outerHTML" {"History"}
+                    button class="text-sm text-neutral-400" hx-get=(format!("{}/edit", self.url())) hx-target=(format!("#{}", id)) hx-swap="outerHTML" {"Edit"}
+                    input type="checkbox" checked[self.done] hx-post=(format!("{}/done", self.url())) hx-include="#filter, #sort, #tag" hx-trigger="click" hx-target=(target) hx-swap="outerHTML" ;
+                    button class="text-sm text-red-500" hx-delete=(self.url()) hx-target=(target) hx-swap="outerHTML" {"Delete"}
+                }
+                div id=(format!("history-{}", self.id)) {}
+                @if self.done && open > 0 {
+                    button class="self-start ml-8 text-sm text-blue-400" hx-post=(format!("{}/complete-subtasks", self.url())) hx-target=(format!("#{}", id)) hx-swap="outerHTML" {
+                        @if open == 1 {
+                            "Also complete the open subtask"
+                        } @else {
+                            "Also complete the " (open) " open subtasks"
+                        }
+                    }
+                }
+                @if !subtasks.is_empty() {
+                    details open class="ml-8" {
+                        summary class="text-sm text-neutral-400 cursor-pointer" { "Subtasks" }
+                        ul class="flex flex-col gap-1" data-reorder=(format!("/lists/{}/reorder", self.list_id)) {
+                            @for subtask in subtasks {
+                                (subtask.render_item(&[], None, query))
+                            }
+                        }
+                    }
+                }
+                @if self.parent_id.is_none() {
+                    div id=(format!("subtask-form-{}", self.id)) {}
+                }
+            }
+        )
+    }
+
+    /// Renders the form to add a subtask, which replaces the placeholder
+    /// below the item.
+    pub fn render_subtask_form(&self, value: &str, error: Option<&str>) -> Markup {
+        html!(
+            div id=(format!("subtask-form-{}", self.id)) class="ml-8 flex flex-col gap-1" {
+                form class="flex flex-row gap-4" hx-post=(format!("{}/subtasks", self.url())) hx-target=(format!("#todo-{}", self.id)) hx-swap="outerHTML" {
+                    input name="prompt" value=(value) autofocus placeholder="New subtask" class="flex-1 border rounded border-neutral-400 text-sm px-4 py-2 bg-black" ;
+                    button class="rounded bg-blue-500 px-4 py-2" {"Add"}
+                    button type="button" class="rounded border border-neutral-400 px-4 py-2" hx-get=(self.url()) hx-target=(format!("#todo-{}", self.id)) hx-swap="outerHTML" {"Cancel"}
+                }
+                @if let Some(error) = error {
+                    div class="text-sm text-red-500" { (error) }
+                }
+            }
+        )
+    }
+
+    /// Renders the item as a form to change the name, the due date, the
+    /// priority, the tags, the repeat rule and whether it is a routine. The
+    /// values of the form are shown instead of the stored ones, e.g. to keep an
+    /// invalid input.
+    pub fn render_edit(&self, form: &EditForm, error: Option<&str>) -> Markup {
+        let id = format!("todo-{}", self.id);
+        html!(
+            li id=(id) class="flex flex-col gap-1"{
+                form class="flex flex-row gap-4" hx-post=(format!("{}/edit", self.url())) hx-target=(format!("#{}", id)) hx-swap="outerHTML" {
+                    input name="name" value=(form.name) autofocus class="flex-1 border rounded border-neutral-400 text-sm px-4 py-2 bg-black" ;
+                    input name="due" type="date" value=(form.due) class="border rounded border-neutral-400 text-sm px-4 py-2 bg-black" ;
+                    (Priority::render_select(form.priority))
+                    input name="tags" value=(form.tags) placeholder="Tags" title="Tags, separated by spaces" class="w-40 border rounded border-neutral-400 text-sm px-4 py-2 bg-black" ;
+                    (Recurrence::render_input(&form.repeat))
+                    label class="flex flex-row gap-1 items-center text-sm text-neutral-400" title="Unchecked by the daily reset instead of being archived" {
+                        input type="checkbox" name="routine" value="true" checked[form.routine] ;
+                        "Routine"
+                    }
+                    button class="rounded bg-blue-500 px-4 py-2" {"Save"}
+                    button type="button" class="rounded border border-neutral-400 px-4 py-2" hx-get=(self.url()) hx-target=(format!("#{}", id)) hx-swap="outerHTML" {"Cancel"}
+                }
+                @if let Some(error) = error {
+                    div class="text-sm text-red-500" { (error) }
+                }
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn parse_rules() {
+        assert_eq!(Recurrence::parse(""), Ok(None));
+        assert_eq!(Recurrence::parse(" Daily "), Ok(Some(Recurrence::Daily)));
+        assert_eq!(
+            Recurrence::parse("weekly fri, mon,fri"),
+            Ok(Some(Recurrence::Weekly(vec![Weekday::Mon, Weekday::Fri])))
+        );
+        assert_eq!(
+            Recurrence::parse("weekly Tuesday"),
+            Ok(Some(Recurrence::Weekly(vec![Weekday::Tue])))
+        );
+        assert_eq!(
+            Recurrence::parse("monthly 31"),
+            Ok(Some(Recurrence::Monthly(31)))
+        );
+        assert_eq!(
+            Recurrence::parse("every 1 day"),
+            Ok(Some(Recurrence::Days(1)))
+        );
+        assert_eq!(Recurrence::parse("every 3"), Ok(Some(Recurrence::Days(3))));
+        for example in Recurrence::EXAMPLES {
+            let recurrence = Recurrence::parse(example).unwrap().unwrap();
+            assert_eq!(recurrence.to_string(), example);
+        }
+    }
+
+    #[test]
+    fn invalid_rules() {
+        for rule in [
+            "yearly",
+            "weekly",
+            "weekly someday",
+            "monthly",
+            "monthly 0",
+            "monthly 32",
+            "monthly 1 15",
+            "every 0 days",
+            "every 367 days",
+            "every -1 days",
+            "every 3 weeks",
+            "daily twice",
+        ] {
+            assert!(Recurrence::parse(rule).is_err(), "{}", rule);
+        }
+        assert!(Recurrence::try_from(String::new()).is_err());
+    }
+
+    #[test]
+    fn month_end() {
+        let monthly = Recurrence::Monthly(31);
+        assert_eq!(monthly.next(date(2024, 1, 31)), Some(date(2024, 2, 29)));
+        assert_eq!(monthly.next(date(2023, 1, 31)), Some(date(2023, 2, 28)));
+        assert_eq!(monthly.next(date(2024, 2, 29)), Some(date(2024, 3, 31)));
+        assert_eq!(monthly.next(date(2024, 4, 15)), Some(date(2024, 4, 30)));
+        assert_eq!(monthly.next(date(2024, 12, 31)), Some(date(2025, 1, 31)));
+        let first = Recurrence::Monthly(1);
+        assert_eq!(first.next(date(2024, 1, 1)), Some(date(2024, 2, 1)));
+        assert_eq!(first.next(date(2024, 12, 31)), Some(date(2025, 1, 1)));
+    }
+
+    #[test]
+    fn weekdays() {
+        // 2024-12-27 is a Friday
+        let friday = date(2024, 12, 27);
+        let weekly = Recurrence::Weekly(vec![Weekday::Mon, Weekday::Fri]);
+        assert_eq!(weekly.next(friday), Some(date(2024, 12, 30)));
+        assert_eq!(weekly.next(date(2024, 12, 30)), Some(date(2025, 1, 3)));
+        let fridays = Recurrence::Weekly(vec![Weekday::Fri]);
+        assert_eq!(fridays.next(friday), Some(date(2025, 1, 3)));
+        assert_eq!(Recurrence::Weekdays.next(friday), Some(date(2024, 12, 30)));
+        assert_eq!(
+            Recurrence::Weekdays.next(date(2024, 12, 28)),
+            Some(date(2024, 12, 30))
+        );
+        assert_eq!(
+            Recurrence::Weekdays.next(date(2024, 12, 30)),
+            Some(date(2024, 12, 31))
+        );
+    }
+
+    #[test]
+    fn days() {
+        assert_eq!(
+            Recurrence::Daily.next(date(2024, 12, 31)),
+            Some(date(2025, 1, 1))
+        );
+        assert_eq!(
+            Recurrence::Days(3).next(date(2024, 2, 27)),
+            Some(date(2024, 3, 1))
+        );
+        assert_eq!(Recurrence::Daily.next(NaiveDate::MAX), None);
+    }
+}
@@ -0,0 +1,663 @@
+//! The todo.txt format (http://todotxt.org): one todo per line, e.g.
+//! `x (A) Call mom +family @phone due:2024-12-31`.
+//!
+//! Priorities map to letters, `(A)` is urgent, `(B)` high and `(D)` low,
+//! normal todos have none. Tags are written as `+project` and contexts as
+//! `@context`. Subtasks refer to their todo with `parent:` and the `id:` of the
+//! todo. Words of the name which would be read as something else, like
+//! `+followup` or `due:friday`, are written with a backslash, e.g. `\+followup`.
+//!
+//! With `TODO_TXT` a list is kept in sync with a todo.txt file, so it can be
+//! edited in the web UI and in a text editor.
+
+use std::{collections::HashMap, fs, io::ErrorKind, path::PathBuf, sync::Mutex, time::Duration};
+
+use actix_web::web;
+use chrono::{Datelike, NaiveDate};
+
+use crate::{
+    audit::{self, Action},
+    events::TodoEvent,
+    export::Format,
+    import::{self, Draft},
+    schedule_next,
+    store::StoreError,
+    tags,
+    todo::{self, Priority, Recurrence, Tag, TagColor, Todo},
+    AppState,
+};
+
+/// How often the file is checked for changes.
+const INTERVAL: Duration = Duration::from_secs(2);
+
+fn priority_letter(priority: Priority) -> Option<char> {
+    match priority {
+        Priority::Urgent => Some('A'),
+        Priority::High => Some('B'),
+        Priority::Normal => None,
+        Priority::Low => Some('D'),
+    }
+}
+
+fn parse_priority(letter: char) -> Option<Priority> {
+    match letter {
+        'A' => Some(Priority::Urgent),
+        'B' => Some(Priority::High),
+        'C' => Some(Priority::Normal),
+        'D'..='Z' => Some(Priority::Low),
+        _ => None,
+    }
+}
+
+fn parse_date(word: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(word, "%Y-%m-%d").ok()
+}
+
+/// A priority like `(A)` in front of the name.
+fn is_priority(word: &str) -> bool {
+    word.len() == 3 && word.starts_with('(') && word.ends_with(')')
+}
+
+/// The `key:value` pairs which are read as fields of the todo.
+const KEYS: [&str; 5] = ["due", "rec", "pri", "id", "parent"];
+
+/// Splits a `+project`, `#tag` or `@context` into its prefix and the tag.
+fn split_tag(word: &str) -> Option<(char, String)> {
+    let prefix = word.chars().next().filter(|c| "+#@".contains(*c))?;
+    let tag = todo::validate_tag(&word[prefix.len_utf8()..]).ok()?;
+    Some((prefix, tag))
+}
+
+/// Whether the word of a name would be read as something else: a tag, a
+/// field, or a date, priority or `x` in front of the name.
+fn needs_escape(word: &str, first: bool) -> bool {
+    word.starts_with('\\')
+        || split_tag(word).is_some()
+        || word
+            .split_once(':')
+            .is_some_and(|(key, _)| KEYS.contains(&key))
+        || (first && (word == "x" || is_priority(word) || parse_date(word).is_some()))
+}
+
+/// `rec:` values of other todo.txt tools like `1d`, `+2w` or `1b` (business
+/// days), besides the rules of this app with dashes, e.g. `weekly-mon,fri`.
+/// Weekly and monthly rules start from the due date.
+fn parse_recurrence(value: &str, due: Option<NaiveDate>) -> Result<Recurrence, &'static str> {
+    let short = value.strip_prefix('+').unwrap_or(value);
+    if let Some(unit) = short.chars().last().filter(|unit| "dbwm".contains(*unit)) {
+        let count = match &short[..short.len() - 1] {
+            "" => Some(1),
+            count => count.parse::<u32>().ok(),
+        };
+        if let Some(count @ 1..) = count {
+            let start = due.unwrap_or_else(todo::today);
+            return match (unit, count) {
+                ('d', 1) => Ok(Recurrence::Daily),
+                ('d', days) => Ok(Recurrence::Days(days)),
+                ('b', 1) => Ok(Recurrence::Weekdays),
+                ('w', 1) => Ok(Recurrence::Weekly(vec![start.weekday()])),
+                ('w', weeks) => Ok(Recurrence::Days(weeks * 7)),
+                ('m', 1) => Ok(Recurrence::Monthly(start.day())),
+                _ => Err("The repeat rule is not supported"),
+            };
+        }
+    }
+    Recurrence::try_from(value.replace('-', " "))
+}
+
+fn format_recurrence(recurrence: &Recurrence) -> String {
+    match recurrence {
+        Recurrence::Daily => "1d".to_string(),
+        Recurrence::Days(days) => format!("{}d", days),
+        Recurrence::Weekdays => "1b".to_string(),
+        other => other.to_string().replace(' ', "-"),
+    }
+}
+
+/// Parses a line into a draft. Unknown `key:value` pairs, like links, stay in
+/// the name, as well as words escaped with a backslash.
+pub fn parse_line(line: &str) -> Result<Draft, &'static str> {
+    let mut words = line.split_whitespace().peekable();
+    let mut draft = Draft::default();
+    if words.next_if_eq(&"x").is_some() {
+        draft.done = true;
+        draft.completed = words
+            .next_if(|word| parse_date(word).is_some())
+            .and_then(parse_date);
+    }
+    if let Some(word) = words.next_if(|word| is_priority(word)) {
+        let letter = word.chars().nth(1).unwrap_or_default();
+        draft.priority =
+            parse_priority(letter).ok_or("The priority must be a letter from A to Z")?;
+    }
+    draft.created = words
+        .next_if(|word| parse_date(word).is_some())
+        .and_then(parse_date);
+    let mut name = vec![];
+    let mut recurrence = None;
+    for word in words {
+        if let Some(word) = word.strip_prefix('\\') {
+            name.push(word);
+            continue;
+        }
+        match word.split_once(':') {
+            Some(("due", date)) => {
+                draft.due = Some(parse_date(date).ok_or("The due date must look like 2024-12-31")?)
+            }
+            Some(("rec", rule)) => recurrence = Some(rule),
+            Some(("pri", letter)) if letter.len() == 1 => {
+                draft.priority = letter
+                    .chars()
+                    .next()
+                    .and_then(parse_priority)
+                    .ok_or("The priority must be a letter from A to Z")?
+            }
+            Some(("id", key)) if !key.is_empty() => draft.key = Some(key.to_string()),
+            Some(("parent", key)) if !key.is_empty() => draft.parent_key = Some(key.to_string()),
+            _ => {
+                let (tags, tag) = match split_tag(word) {
+                    Some(('@', context)) => (&mut draft.contexts, context),
+                    Some((_, tag)) => (&mut draft.tags, tag),
+                    None => {
+                        name.push(word);
+                        continue;
+                    }
+                };
+                if !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+        }
+    }
+    if let Some(rule) = recurrence {
+        draft.recurrence = Some(parse_recurrence(rule, draft.due)?);
+    }
+    draft.name = todo::validate_name(&name.join(" "))?;
+    Ok(draft)
+}
+
+/// Formats the todo as a line. Completed todos keep their priority as `pri:`,
+/// because todo.txt drops the priority on completion. `key` is written as
+/// `id:` for todos with subtasks.
+pub fn format_line(todo: &Todo, key: Option<u128>) -> String {
+    let mut words = vec![];
+    let letter = priority_letter(todo.priority);
+    if todo.done {
+        words.push("x".to_string());
+        // a single date after `x` is the completion date
+        if let Some(completed) = todo.completed {
+            words.push(completed.to_string());
+            words.extend(todo.created.map(|created| created.to_string()));
+        }
+    } else {
+        words.extend(letter.map(|letter| format!("({})", letter)));
+        words.extend(todo.created.map(|created| created.to_string()));
+    }
+    for (index, word) in todo.name.split_whitespace().enumerate() {
+        match needs_escape(word, index == 0) {
+            true => words.push(format!("\\{}", word)),
+            false => words.push(word.to_string()),
+        }
+    }
+    for tag in &todo.tags {
+        words.push(format!("+{}", tag.name));
+    }
+    for context in &todo.contexts {
+        words.push(format!("@{}", context));
+    }
+    if let Some(due) = todo.due {
+        words.push(format!("due:{}", due));
+    }
+    if let Some(recurrence) = &todo.recurrence {
+        words.push(format!("rec:{}", format_recurrence(recurrence)));
+    }
+    if let (true, Some(letter)) = (todo.done, letter) {
+        words.push(format!("pri:{}", letter));
+    }
+    if let Some(key) = key {
+        words.push(format!("id:{}", key));
+    }
+    if let Some(parent_id) = todo.parent_id {
+        words.push(format!("parent:{}", parent_id));
+    }
+    words.join(" ")
+}
+
+/// Reads the file to sync from `TODO_TXT` and the id of the list from
+/// `TODO_TXT_LIST`. Without `TODO_TXT` nothing is synced.
+pub fn sync_config() -> std::io::Result<Option<(PathBuf, u128)>> {
+    let Ok(path) = std::env::var("TODO_TXT") else {
+        return Ok(None);
+    };
+    match std::env::var("TODO_TXT_LIST").map(|id| id.parse()) {
+        Ok(Ok(list_id)) => Ok(Some((PathBuf::from(path), list_id))),
+        _ => Err(std::io::Error::other(
+            "TODO_TXT_LIST must be the id of the list to sync with TODO_TXT",
+        )),
+    }
+}
+
+/// Whether the line still describes the todo. Lines without dates match todos
+/// with dates, so lines typed in the editor are kept as they are.
+fn line_matches(line: &str, todo: &Todo, key: Option<u128>) -> bool {
+    let Ok(draft) = parse_line(line) else {
+        return false;
+    };
+    let tags: Vec<&str> = todo.tags.iter().map(|tag| tag.name.as_str()).collect();
+    draft.name == todo.name
+        && draft.done == todo.done
+        && draft.due == todo.due
+        && draft.priority == todo.priority
+        && draft.tags == tags
+        && draft.contexts == todo.contexts
+        && draft.recurrence == todo.recurrence
+        && draft.key == key.map(|key| key.to_string())
+        && draft.parent_key == todo.parent_id.map(|id| id.to_string())
+        && draft
+            .created
+            .is_none_or(|created| Some(created) == todo.created)
+        && draft
+            .completed
+            .is_none_or(|completed| Some(completed) == todo.completed)
+}
+
+/// Keeps a list and a todo.txt file in sync. Changes in the file are applied
+/// to the list, then the list is written back. Lines of unchanged todos are
+/// written as they were read, so unknown `key:value` pairs and the order of
+/// the words survive. Changed todos get a new line.
+pub struct Sync {
+    path: PathBuf,
+    list_id: u128,
+    /// The content of the file after the last sync, `None` before the first.
+    last: Option<String>,
+    /// A content which could not be read, so its errors are logged once.
+    rejected: Option<String>,
+    /// The line of every todo in the file.
+    lines: HashMap<u128, String>,
+}
+
+impl Sync {
+    pub fn new(path: PathBuf, list_id: u128) -> Self {
+        Sync {
+            path,
+            list_id,
+            last: None,
+            rejected: None,
+            lines: HashMap::new(),
+        }
+    }
+
+    /// The file content for the todos, each todo followed by its subtasks.
+    fn render(&mut self, todos: &[Todo]) -> String {
+        let mut ordered: Vec<&Todo> = vec![];
+        for todo in todos.iter().filter(|todo| todo.parent_id.is_none()) {
+            ordered.push(todo);
+            ordered.extend(todos.iter().filter(|item| item.parent_id == Some(todo.id)));
+        }
+        let mut lines = HashMap::new();
+        let mut content = String::new();
+        for todo in ordered {
+            let parent = todos.iter().any(|item| item.parent_id == Some(todo.id));
+            let key = parent.then_some(todo.id);
+            let line = match self.lines.get(&todo.id) {
+                Some(line) if line_matches(line, todo, key) => line.clone(),
+                _ => format_line(todo, key),
+            };
+            content.push_str(&line);
+            content.push('\n');
+            lines.insert(todo.id, line);
+        }
+        self.lines = lines;
+        content
+    }
+
+    /// Applies the lines of the file to the list. Lines are matched to the
+    /// todos by their text, then by the name, other lines become new todos.
+    /// Todos whose line was removed are deleted, todos added in the web UI since
+    /// the last sync are kept. Returns `false` if a line could not be read.
+    fn apply(&mut self, state: &mut AppState, content: &str) -> Result<bool, StoreError> {
+        let (drafts, errors) = import::parse(Format::Txt, content);
+        if !errors.is_empty() {
+            if self.rejected.as_deref() != Some(content) {
+                for error in &errors {
+                    eprintln!(
+                        "todo.txt sync: {} line {}: {}",
+                        self.path.display(),
+                        error.line,
+                        error.message
+                    );
+                }
+                self.rejected = Some(content.to_string());
+            }
+            return Ok(false);
+        }
+        self.rejected = None;
+        let raw: Vec<&str> = content.lines().collect();
+        let todos = state.store.list(Some(self.list_id))?;
+        let mut matched: Vec<Option<Todo>> = vec![None; drafts.len()];
+        let mut taken = vec![];
+        for (draft, slot) in drafts.iter().zip(matched.iter_mut()) {
+            let line = raw[draft.line - 1];
+            let found = todos.iter().find(|todo| {
+                !taken.contains(&todo.id)
+                    && self.lines.get(&todo.id).map(String::as_str) == Some(line)
+            });
+            if let Some(todo) = found {
+                taken.push(todo.id);
+                *slot = Some(todo.clone());
+            }
+        }
+        for (draft, slot) in drafts.iter().zip(matched.iter_mut()) {
+            if slot.is_none() {
+                let found = todos
+                    .iter()
+                    .find(|todo| !taken.contains(&todo.id) && todo.name == draft.name);
+                if let Some(todo) = found {
+                    taken.push(todo.id);
+                    *slot = Some(todo.clone());
+                }
+            }
+        }
+        // only todos of the last sync can have been removed from the file
+        let removed: Vec<Todo> = todos
+            .iter()
+            .filter(|todo| !taken.contains(&todo.id) && self.lines.contains_key(&todo.id))
+            .cloned()
+            .collect();
+
+        let user_id = state
+            .store
+            .get_list(self.list_id)?
+            .and_then(|list| list.user_id);
+        let mut ids: Vec<Option<u128>> = vec![None; drafts.len()];
+        let top = (0..drafts.len()).filter(|&i| drafts[i].parent.is_none());
+        let subtasks = (0..drafts.len()).filter(|&i| drafts[i].parent.is_some());
+        let mut changed = !removed.is_empty();
+        for i in top.chain(subtasks) {
+            let draft = &drafts[i];
+            let tags = match user_id {
+                Some(user_id) => tags::resolve(&*state.store, user_id, draft.tags.clone())?,
+                // lists from before there were user accounts
+                None => draft
+                    .tags
+                    .iter()
+                    .map(|name| Tag {
+                        name: name.clone(),
+                        color: TagColor::for_name(name),
+                    })
+                    .collect(),
+            };
+            let parent_id = draft.parent.and_then(|parent| ids[parent]);
+            let todo = match &matched[i] {
+                Some(old) => {
+                    let mut todo = Todo {
+                        parent_id,
+                        name: draft.name.clone(),
+                        due: draft.due,
+                        priority: draft.priority,
+                        tags,
+                        contexts: draft.contexts.clone(),
+                        recurrence: draft.recurrence.clone(),
+                        created: draft.created.or(old.created),
+                        ..old.clone()
+                    };
+                    todo.set_done(draft.done);
+                    if draft.completed.is_some() {
+                        todo.completed = draft.completed;
+                    }
+                    if &todo != old {
+                        state.store.update(&todo)?;
+                        audit::record_changes(state, None, old, &todo)?;
+                        if todo.done && !old.done {
+                            schedule_next(state, None, &todo)?;
+                        }
+                        changed = true;
+                    }
+                    todo
+                }
+                None => {
+                    let todo = state.store.create(Todo {
+                        list_id: self.list_id,
+                        parent_id,
+                        name: draft.name.clone(),
+                        done: draft.done,
+                        due: draft.due,
+                        priority: draft.priority,
+                        tags,
+                        contexts: draft.contexts.clone(),
+                        recurrence: draft.recurrence.clone(),
+                        created: draft.created,
+                        completed: draft.completed.or(draft.done.then(todo::today)),
+                        ..Todo::default()
+                    })?;
+                    audit::created(state, None, &todo)?;
+                    changed = true;
+                    todo
+                }
+            };
+            self.lines.insert(todo.id, raw[draft.line - 1].to_string());
+            ids[i] = Some(todo.id);
+        }
+        for todo in &removed {
+            // a subtask is gone already if its todo was removed as well
+            if state.store.get(todo.id)?.is_none() {
+                continue;
+            }
+            let deleted = state.store.with_subtasks(todo)?;
+            state.store.remove(todo.id)?;
+            for todo in &deleted {
+                self.lines.remove(&todo.id);
+                audit::record(state, None, todo, Action::Deleted)?;
+            }
+        }
+        // the todos of the file take the places of the todos in the list
+        let order: Vec<u128> = ids.into_iter().flatten().collect();
+        let before: Vec<u128> = state
+            .store
+            .list(Some(self.list_id))?
+            .iter()
+            .map(|todo| todo.id)
+            .filter(|id| order.contains(id))
+            .collect();
+        if before != order {
+            state.store.reorder(self.list_id, &order)?;
+            changed = true;
+        }
+        if changed {
+            state.events.send(TodoEvent::ListChanged(self.list_id));
+        }
+        Ok(true)
+    }
+
+    /// Applies the changes of the content of the file, if there are any, and
+    /// returns the new content. `None` if the list does not exist (yet) or the
+    /// content could not be read.
+    fn update(
+        &mut self,
+        state: &mut AppState,
+        content: &str,
+    ) -> Result<Option<String>, StoreError> {
+        if state.store.get_list(self.list_id)?.is_none() {
+            return Ok(None);
+        }
+        let edited = match &self.last {
+            Some(last) => last != content,
+            None => !content.is_empty(),
+        };
+        if edited && !self.apply(state, content)? {
+            return Ok(None);
+        }
+        let todos = state.store.list(Some(self.list_id))?;
+        Ok(Some(self.render(&todos)))
+    }
+
+    fn read(&self) -> Result<String, StoreError> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => Ok(content),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(String::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Applies the changes of the file, if there are any, and writes the list
+    /// to the file. The state is only locked in between, so reading and
+    /// writing the file does not hold up the requests. If the file was saved
+    /// again in the meantime, it is not written, the next run merges it.
+    pub fn run_once(&mut self, data: &Mutex<AppState>) -> Result<(), StoreError> {
+        let content = self.read()?;
+        let rendered = match data.lock() {
+            Ok(mut state) => self.update(&mut state, &content)?,
+            Err(_) => {
+                eprintln!("todo.txt sync: mutex lock failed");
+                return Ok(());
+            }
+        };
+        let Some(rendered) = rendered else {
+            return Ok(());
+        };
+        if rendered != content {
+            // write to a temporary file first so an editor never reads a half written file
+            let tmp = self.path.with_extension("tmp");
+            fs::write(&tmp, &rendered)?;
+            if self.read()? != content {
+                fs::remove_file(&tmp)?;
+                self.last = Some(content);
+                return Ok(());
+            }
+            fs::rename(&tmp, &self.path)?;
+        }
+        self.last = Some(rendered);
+        Ok(())
+    }
+}
+
+/// Syncs the list and the file every few seconds.
+pub async fn run(data: web::Data<Mutex<AppState>>, mut sync: Sync) {
+    loop {
+        if let Err(err) = sync.run_once(&data) {
+            eprintln!("todo.txt sync failed: {}", err);
+        }
+        actix_web::rt::time::sleep(INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The todo the sync would store for the line.
+    fn to_todo(draft: Draft) -> (Todo, Option<u128>) {
+        let todo = Todo {
+            parent_id: draft.parent_key.map(|key| key.parse().unwrap()),
+            name: draft.name,
+            done: draft.done,
+            due: draft.due,
+            priority: draft.priority,
+            tags: draft
+                .tags
+                .iter()
+                .map(|name| Tag {
+                    name: name.clone(),
+                    color: TagColor::for_name(name),
+                })
+                .collect(),
+            contexts: draft.contexts,
+            recurrence: draft.recurrence,
+            created: draft.created,
+            completed: draft.completed,
+            ..Todo::default()
+        };
+        (todo, draft.key.map(|key| key.parse().unwrap()))
+    }
+
+    fn round_trip(line: &str) -> String {
+        let (todo, key) = to_todo(parse_line(line).unwrap());
+        format_line(&todo, key)
+    }
+
+    #[test]
+    fn escaped_words() {
+        for line in [
+            "Email bob \\+followup",
+            "Pay rent \\due:friday",
+            "Read \\#1 and \\@home",
+            "\\x marks the spot",
+            "\\(A) is no priority",
+            "\\2024-01-01 party",
+            "Open C:\\\\temp \\\\share",
+            "x 2024-12-31 \\2025-01-01 party",
+        ] {
+            assert_eq!(round_trip(line), line);
+        }
+        let draft = parse_line("Email bob \\+followup \\due:friday").unwrap();
+        assert_eq!(draft.name, "Email bob +followup due:friday");
+        assert!(draft.tags.is_empty());
+        assert_eq!(draft.due, None);
+        // only the first word can be read as a date or priority
+        assert_eq!(
+            round_trip("Party on 2024-01-01 (A) x"),
+            "Party on 2024-01-01 (A) x"
+        );
+    }
+
+    #[test]
+    fn contexts_and_tags() {
+        let line = "Call mom +family +phone @phone @home";
+        assert_eq!(round_trip(line), line);
+        let draft = parse_line("Call #family mom @phone +family").unwrap();
+        assert_eq!(draft.name, "Call mom");
+        assert_eq!(draft.tags, vec!["family".to_string()]);
+        assert_eq!(draft.contexts, vec!["phone".to_string()]);
+        assert_eq!(
+            round_trip("Call #family mom @phone +family"),
+            "Call mom +family @phone"
+        );
+    }
+
+    #[test]
+    fn dates() {
+        for line in [
+            "x 2024-12-31 2024-12-01 Pay rent +home",
+            "x 2024-12-31 Pay rent",
+            "x Pay rent",
+            "(A) 2024-12-01 Call mom due:2024-12-24",
+            "2024-12-01 Call mom",
+        ] {
+            assert_eq!(round_trip(line), line);
+        }
+        let draft = parse_line("x 2024-12-31 2024-12-01 Pay rent").unwrap();
+        assert!(draft.done);
+        assert_eq!(draft.completed, NaiveDate::from_ymd_opt(2024, 12, 31));
+        assert_eq!(draft.created, NaiveDate::from_ymd_opt(2024, 12, 1));
+        let draft = parse_line("2024-12-01 Call mom").unwrap();
+        assert_eq!(draft.created, NaiveDate::from_ymd_opt(2024, 12, 1));
+        assert_eq!(draft.completed, None);
+    }
+
+    #[test]
+    fn keys() {
+        for line in [
+            "x 2024-12-31 Pay rent pri:B",
+            "(D) Water plants rec:weekly-mon,fri id:3",
+            "Buy milk parent:3",
+            "Stretch rec:1d",
+            "Stand-up rec:1b",
+            "Backup rec:3d",
+            "Rent rec:monthly-31",
+            "See http://example.com id",
+        ] {
+            assert_eq!(round_trip(line), line);
+        }
+        let draft = parse_line("x Pay rent pri:A").unwrap();
+        assert_eq!(draft.priority, Priority::Urgent);
+        assert_eq!(parse_line("(C) Normal").unwrap().priority, Priority::Normal);
+        assert_eq!(
+            parse_line("Plan rec:2w").unwrap().recurrence,
+            Some(Recurrence::Days(14))
+        );
+        assert!(parse_line("Pay due:friday").is_err());
+        assert!(parse_line("Pay rec:yearly").is_err());
+        assert!(parse_line("(1) Pay").is_err());
+        assert!(parse_line("x 2024-12-31").is_err());
+    }
+}
@@ -0,0 +1,304 @@
+//! Undo for toggling, deleting and archiving todos. Every change gets an entry
+//! in a short log, which the toast below the page can revert for a few
+//! seconds. The entries belong to the session which made the change.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use actix_session::Session;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use maud::{html, Markup};
+
+use crate::{
+    audit::{self, Action},
+    auth::CurrentUser,
+    events::TodoEvent,
+    path_id, posted_view,
+    todo::{self, Todo},
+    ApiError, AppState, ViewQuery,
+};
+
+/// Session key of the random id which separates the undo logs of the sessions.
+const SESSION_KEY: &str = "undo_session";
+
+/// How long the failure message of a late undo is shown.
+const MESSAGE_SECONDS: u64 = 3;
+
+/// Reads how many seconds a change can be undone from `TODO_UNDO_SECONDS`.
+/// Without it changes can be undone for 10 seconds.
+pub fn undo_time() -> std::io::Result<Duration> {
+    match std::env::var("TODO_UNDO_SECONDS") {
+        Ok(seconds) => match seconds.parse() {
+            Ok(seconds @ 1..) => Ok(Duration::from_secs(seconds)),
+            _ => Err(std::io::Error::other(
+                "TODO_UNDO_SECONDS must be a positive number",
+            )),
+        },
+        Err(_) => Ok(Duration::from_secs(10)),
+    }
+}
+
+/// The id of the session in the undo log, created on first use.
+pub fn session_id(session: &Session) -> Result<u64, ApiError> {
+    if let Ok(Some(id)) = session.get::<u64>(SESSION_KEY) {
+        return Ok(id);
+    }
+    let id = OsRng.next_u64();
+    match session.insert(SESSION_KEY, id) {
+        Ok(()) => Ok(id),
+        Err(_) => Err(ApiError { name: "session" }),
+    }
+}
+
+/// A change which can be undone.
+pub enum Change {
+    /// The todo was checked or unchecked. `next` is the occurrence which was
+    /// added by completing a recurring todo.
+    Toggled {
+        id: u128,
+        done: bool,
+        next: Option<u128>,
+    },
+    /// The todos were deleted, subtasks included.
+    Removed(Vec<Todo>),
+    /// The completed todos were archived, subtasks included.
+    Archived(Vec<u128>),
+}
+
+struct Operation {
+    id: u128,
+    session: u64,
+    list_id: u128,
+    time: Instant,
+    change: Change,
+}
+
+/// The changes of the last seconds, shared by all sessions.
+pub struct UndoLog {
+    operations: Vec<Operation>,
+    last_id: u128,
+    /// How long a change can be undone.
+    pub duration: Duration,
+}
+
+impl UndoLog {
+    pub fn new(duration: Duration) -> Self {
+        UndoLog {
+            operations: vec![],
+            last_id: 0,
+            duration,
+        }
+    }
+
+    /// Records a change and returns the id of the operation for the undo
+    /// button. Expired operations are dropped on the way.
+    pub fn record(&mut self, session: u64, list_id: u128, change: Change) -> u128 {
+        let duration = self.duration;
+        self.operations
+            .retain(|operation| operation.time.elapsed() < duration);
+        self.last_id += 1;
+        self.operations.push(Operation {
+            id: self.last_id,
+            session,
+            list_id,
+            time: Instant::now(),
+            change,
+        });
+        self.last_id
+    }
+
+    /// Removes the operation from the log if it belongs to the session and has
+    /// not expired yet.
+    fn take(&mut self, session: u64, id: u128) -> Option<Operation> {
+        let index = self.operations.iter().position(|operation| {
+            operation.id == id
+                && operation.session == session
+                && operation.time.elapsed() < self.duration
+        })?;
+        Some(self.operations.remove(index))
+    }
+}
+
+/// The empty placeholder of the toast on the list page.
+pub fn render_empty() -> Markup {
+    html! {
+        div #toast {}
+    }
+}
+
+/// A toast which hides itself after the given time.
+fn render_message(content: Markup, duration: Duration, oob: bool) -> Markup {
+    html! {
+        div #toast hx-swap-oob=[oob.then_some("true")]
+        class="fixed bottom-4 right-4 flex flex-row gap-4 items-center rounded bg-neutral-800 px-4 py-2 text-sm"
+        hx-get="/toast" hx-trigger=(format!("load delay:{}s", duration.as_secs())) hx-swap="outerHTML" {
+            (content)
+        }
+    }
+}
+
+/// A toast with the undo button of the operation, swapped out of band.
+pub fn render_toast(log: &UndoLog, operation_id: u128, message: &str) -> Markup {
+    render_message(
+        html! {
+            span { (message) }
+            button class="text-blue-400" hx-post=(format!("/undo/{}", operation_id)) hx-include="#filter, #sort, #tag" hx-target="#toast" hx-swap="outerHTML" { "Undo" }
+        },
+        log.duration,
+        true,
+    )
+}
+
+#[get("/toast")]
+async fn dismiss() -> Markup {
+    render_empty()
+}
+
+/// Out-of-band swaps which bring the list on the page up to date after an
+/// undo. The todos of `inserted` are put back, each before the next todo of
+/// the view which is already on the page, the todos of `refreshed` are
+/// rendered again. Todos outside of the view are left out.
+fn render_restored(
+    todos: &[Todo],
+    view: &ViewQuery,
+    inserted: &[u128],
+    refreshed: &[u128],
+) -> Markup {
+    let mut top: Vec<Todo> = todos
+        .iter()
+        .filter(|todo| todo.parent_id.is_none() && view.matches(todo))
+        .cloned()
+        .collect();
+    view.sort.apply(&mut top);
+    html! {
+        @for (index, todo) in top.iter().enumerate() {
+            @if inserted.contains(&todo.id) {
+                @let swap = match top[index + 1..].iter().find(|next| !inserted.contains(&next.id)) {
+                    Some(next) => format!("beforebegin:#todo-{}", next.id),
+                    None => "beforeend:#todo-list".to_string(),
+                };
+                div hx-swap-oob=(swap) { (todo.render(&todo::subtasks(todos, todo.id))) }
+            } @else if refreshed.contains(&todo.id) {
+                (todo.render_oob(&todo::subtasks(todos, todo.id), "true"))
+            }
+        }
+    }
+}
+
+/// Splits the todos which are back in the list into top-level todos, which
+/// are inserted, and the parents of subtasks, which are rendered again.
+fn split_restored(restored: &[Todo], inserted: &mut Vec<u128>, refreshed: &mut Vec<u128>) {
+    for todo in restored {
+        match todo.parent_id {
+            Some(parent_id) if restored.iter().all(|parent| parent.id != parent_id) => {
+                refreshed.push(parent_id)
+            }
+            Some(_) => {}
+            None => inserted.push(todo.id),
+        }
+    }
+}
+
+/// Reverts the operation. The response puts the restored items back on the
+/// page and updates the statistic, other pages of the list are updated by the
+/// events. htmx sends the view of the page with
+/// `hx-include="#filter, #sort, #tag"`.
+#[post("/undo/{id}")]
+async fn undo(
+    req: HttpRequest,
+    user: CurrentUser,
+    session: Session,
+    data: web::Data<Mutex<AppState>>,
+    form: Option<web::Form<ViewQuery>>,
+) -> Result<HttpResponse, ApiError> {
+    let mut state = match data.lock() {
+        Ok(state) => state,
+        Err(_) => return Err(ApiError { name: "mutex lock" }),
+    };
+    let session = session_id(&session)?;
+    let operation = match state.undo.take(session, path_id(&req, "id")?) {
+        // the list could have been deleted in the meantime
+        Some(operation) if state.store.get_list(operation.list_id)?.is_some() => operation,
+        _ => {
+            let message = html! { span { "The change can no longer be undone" } };
+            return Ok(HttpResponse::Ok().body(
+                render_message(message, Duration::from_secs(MESSAGE_SECONDS), false).into_string(),
+            ));
+        }
+    };
+    let view = posted_view(form);
+    let mut body = render_empty().into_string();
+    let mut inserted = vec![];
+    let mut refreshed = vec![];
+    match operation.change {
+        Change::Toggled { id, done, next } => {
+            if let Some(next) = next.map(|id| state.store.get(id)).transpose()?.flatten() {
+                let removed = state.store.with_subtasks(&next)?;
+                state.store.remove(next.id)?;
+                for todo in &removed {
+                    audit::record(&mut state, Some(user.0), todo, Action::Deleted)?;
+                }
+                match next.parent_id {
+                    Some(parent_id) => refreshed.push(parent_id),
+                    None if view.matches(&next) => {
+                        body.push_str(&next.render_oob(&[], "delete").into_string())
+                    }
+                    None => {}
+                }
+                state.events.send(TodoEvent::Removed(next));
+            }
+            if let Some(old) = state.store.get(id)? {
+                let mut todo = old.clone();
+                todo.set_done(done);
+                state.store.update(&todo)?;
+                audit::record_changes(&mut state, Some(user.0), &old, &todo)?;
+                // the item is on the page if it matched the view after the toggle
+                match todo.parent_id {
+                    Some(parent_id) => refreshed.push(parent_id),
+                    None if view.matches(&old) && !view.matches(&todo) => {
+                        body.push_str(&todo.render_oob(&[], "delete").into_string())
+                    }
+                    None if view.matches(&old) => refreshed.push(todo.id),
+                    None => inserted.push(todo.id),
+                }
+                state.events.send(TodoEvent::Changed(todo));
+            }
+        }
+        Change::Removed(todos) => {
+            let mut restored = vec![];
+            for todo in todos {
+                if state.store.get(todo.id)?.is_none() {
+                    state.store.restore(&todo)?;
+                    audit::record(&mut state, Some(user.0), &todo, Action::Restored)?;
+                    restored.push(todo);
+                }
+            }
+            split_restored(&restored, &mut inserted, &mut refreshed);
+            state.events.send(TodoEvent::ListChanged(operation.list_id));
+        }
+        Change::Archived(ids) => {
+            let mut restored = vec![];
+            for id in ids {
+                if let Some(old) = state.store.get(id)?.filter(|todo| todo.archived) {
+                    let todo = Todo {
+                        archived: false,
+                        ..old
+                    };
+                    state.store.update(&todo)?;
+                    audit::record(&mut state, Some(user.0), &todo, Action::Restored)?;
+                    restored.push(todo);
+                }
+            }
+            split_restored(&restored, &mut inserted, &mut refreshed);
+            state.events.send(TodoEvent::ListChanged(operation.list_id));
+        }
+    }
+    let todos = state.store.list(Some(operation.list_id))?;
+    body.push_str(&render_restored(&todos, &view, &inserted, &refreshed).into_string());
+    Ok(HttpResponse::Ok()
+        .append_header(("HX-Trigger", "changedTodos"))
+        .body(body))
+}
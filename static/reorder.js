@@ -0,0 +1,56 @@
+// Drag and drop of todos by their handle. The new order of the siblings is
+// posted to the `data-reorder` URL of their list. Dragging only works in the
+// manual order, because any other sort would move the todo right back.
+(function () {
+  let dragged = null;
+
+  function listItem(target, list) {
+    while (target && target.parentElement !== list) {
+      target = target.parentElement;
+    }
+    return target;
+  }
+
+  document.addEventListener("dragstart", function (event) {
+    const handle = event.target.closest && event.target.closest("[data-drag-handle]");
+    const sort = document.getElementById("sort");
+    if (!handle || (sort && sort.value !== "manual")) {
+      if (handle) event.preventDefault();
+      return;
+    }
+    dragged = handle.closest("li");
+    event.dataTransfer.effectAllowed = "move";
+    event.dataTransfer.setDragImage(dragged, 0, 0);
+    dragged.classList.add("opacity-50");
+  });
+
+  document.addEventListener("dragover", function (event) {
+    if (!dragged) return;
+    const list = dragged.parentElement;
+    const item = listItem(event.target, list);
+    if (!item) return;
+    event.preventDefault();
+    if (item === dragged) return;
+    const rect = item.getBoundingClientRect();
+    const after = event.clientY > rect.top + rect.height / 2;
+    list.insertBefore(dragged, after ? item.nextSibling : item);
+  });
+
+  document.addEventListener("drop", function (event) {
+    if (dragged) event.preventDefault();
+  });
+
+  document.addEventListener("dragend", function () {
+    if (!dragged) return;
+    const list = dragged.parentElement;
+    dragged.classList.remove("opacity-50");
+    dragged = null;
+    const ids = Array.from(list.children)
+      .map(function (item) { return item.dataset.id; })
+      .filter(Boolean);
+    htmx.ajax("POST", list.dataset.reorder, {
+      swap: "none",
+      values: { order: ids.join(",") },
+    });
+  });
+})();